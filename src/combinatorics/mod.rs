@@ -0,0 +1,729 @@
+// A toolbox for any day to pick from, so not every day uses every iterator
+
+/// Iterator which lends out each item from a buffer it reuses, so nothing is allocated per item
+/// Items come in a fixed order, and nth jumps straight to an item by its index in that order
+#[allow(dead_code)]
+pub(crate) trait LendingIterator {
+    type Item: ?Sized;
+
+    /// Moves on to the next item and lends it out, or None once there are none left
+    fn next(&mut self) -> Option<&Self::Item>;
+
+    /// Skips over n items and lends out the one after, like Iterator::nth
+    fn nth(&mut self, n: u128) -> Option<&Self::Item>;
+
+    /// Number of items in the whole order
+    fn total(&self) -> u128;
+
+    /// Number of items not lent out yet
+    fn remaining(&self) -> u128;
+
+    /// Exact number of items left, like Iterator::size_hint
+    /// Both bounds are None past usize::MAX
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match usize::try_from(self.remaining()) {
+            Ok(remaining) => (remaining, Some(remaining)),
+            Err(_) => (usize::MAX, None),
+        }
+    }
+}
+
+/// Number of ways to pick k things out of n
+/// Panics if it doesn't fit in a u128
+#[allow(dead_code)]
+pub(crate) fn binomial(n: usize, k: usize) -> u128 {
+    if k > n {
+        return 0;
+    }
+    let k = k.min(n - k);
+    // Each step is itself a binomial, so always divides exactly
+    // Dividing before multiplying means only an answer too big to fit can overflow
+    (0..k).fold(1, |acc: u128, i| {
+        let divisor = i as u128 + 1;
+        let shared = gcd(acc, divisor);
+        (acc / shared)
+            .checked_mul((n - i) as u128 / (divisor / shared))
+            .expect("Binomial overflow")
+    })
+}
+
+/// Greatest common divisor
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Every way to pick r of 0..n, each in increasing order, in lexicographic order
+/// Ex: 4 choose 2
+/// 0 1, 0 2, 0 3, 1 2, 1 3, 2 3
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub(crate) struct Combinations {
+    n: usize,
+    indices: Vec<usize>,
+    total: u128,
+    /// Index of the next item to lend out
+    position: u128,
+}
+
+#[allow(dead_code)]
+impl Combinations {
+    pub(crate) fn new(n: usize, r: usize) -> Self {
+        Self {
+            n,
+            indices: vec![0; r],
+            total: binomial(n, r),
+            position: 0,
+        }
+    }
+
+    /// Returns the index of the combination in the order
+    pub(crate) fn rank(&self, combination: &[usize]) -> u128 {
+        let r = self.indices.len();
+        let mut rank = 0;
+        let mut start = 0;
+        for (i, &idx) in combination.iter().enumerate() {
+            // Every combination starting with something lower here comes first
+            for skipped in start..idx {
+                rank += binomial(self.n - skipped - 1, r - i - 1);
+            }
+            start = idx + 1;
+        }
+
+        rank
+    }
+
+    /// Writes the combination at the index into the buffer
+    fn unrank(&mut self, mut rank: u128) {
+        let r = self.indices.len();
+        let mut start = 0;
+        for i in 0..r {
+            let mut idx = start;
+            loop {
+                let skip = binomial(self.n - idx - 1, r - i - 1);
+                if rank < skip {
+                    break;
+                }
+                rank -= skip;
+                idx += 1;
+            }
+            self.indices[i] = idx;
+            start = idx + 1;
+        }
+    }
+
+    /// Moves the buffer on to the next combination
+    /// Ex: for 6 choose 3
+    /// 0 1 5 -> 0 2 3: move the last index that isn't at the end, then the rest back behind it
+    fn step(&mut self) {
+        let (n, r) = (self.n, self.indices.len());
+        let i = (0..r)
+            .rev()
+            .find(|&i| self.indices[i] < n - r + i)
+            .expect("Stepped past the last combination");
+        self.indices[i] += 1;
+        for j in i + 1..r {
+            self.indices[j] = self.indices[j - 1] + 1;
+        }
+    }
+}
+
+impl LendingIterator for Combinations {
+    type Item = [usize];
+
+    fn next(&mut self) -> Option<&[usize]> {
+        if self.position >= self.total {
+            return None;
+        }
+        if self.position == 0 {
+            self.indices
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, idx)| *idx = i);
+        } else {
+            self.step();
+        }
+        self.position += 1;
+
+        Some(&self.indices)
+    }
+
+    fn nth(&mut self, n: u128) -> Option<&[usize]> {
+        let rank = self.position.saturating_add(n);
+        if rank >= self.total {
+            self.position = self.total;
+            return None;
+        }
+        self.unrank(rank);
+        self.position = rank + 1;
+
+        Some(&self.indices)
+    }
+
+    fn total(&self) -> u128 {
+        self.total
+    }
+
+    fn remaining(&self) -> u128 {
+        self.total - self.position
+    }
+}
+
+/// Every ordering of 0..n, in lexicographic order
+/// Ex: 3
+/// 0 1 2, 0 2 1, 1 0 2, 1 2 0, 2 0 1, 2 1 0
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub(crate) struct Permutations {
+    order: Vec<usize>,
+    total: u128,
+    /// Index of the next item to lend out
+    position: u128,
+}
+
+#[allow(dead_code)]
+impl Permutations {
+    /// Panics past n = 34, where n! no longer fits in a u128
+    pub(crate) fn new(n: usize) -> Self {
+        assert!(n <= 34, "{}! doesn't fit in a u128", n);
+        Self {
+            order: (0..n).collect(),
+            total: factorial(n),
+            position: 0,
+        }
+    }
+
+    /// Returns the index of the permutation in the order
+    /// Each place counts how many of the values after it are smaller (its Lehmer code)
+    pub(crate) fn rank(&self, permutation: &[usize]) -> u128 {
+        let n = permutation.len();
+        (0..n)
+            .map(|i| {
+                let smaller = permutation[i + 1..]
+                    .iter()
+                    .filter(|&&val| val < permutation[i])
+                    .count();
+                smaller as u128 * factorial(n - i - 1)
+            })
+            .sum()
+    }
+
+    /// Writes the permutation at the index into the buffer
+    /// The values not placed yet are kept in order at the back, so each one picked
+    /// is rotated to the front of them
+    fn unrank(&mut self, mut rank: u128) {
+        let n = self.order.len();
+        self.order
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, val)| *val = i);
+        for i in 0..n {
+            let place = factorial(n - i - 1);
+            let pick = (rank / place) as usize;
+            rank %= place;
+            self.order[i..=i + pick].rotate_right(1);
+        }
+    }
+
+    /// Moves the buffer on to the next permutation
+    /// Ex: 1 3 2 0 -> 2 0 1 3
+    /// Find the last place followed by a bigger value (1), swap it with the smallest bigger value
+    /// after it (2), then put everything after it back in increasing order
+    fn step(&mut self) {
+        let n = self.order.len();
+        let i = (0..n - 1)
+            .rev()
+            .find(|&i| self.order[i] < self.order[i + 1])
+            .expect("Stepped past the last permutation");
+        let j = (i + 1..n)
+            .rev()
+            .find(|&j| self.order[j] > self.order[i])
+            .unwrap();
+        self.order.swap(i, j);
+        self.order[i + 1..].reverse();
+    }
+}
+
+impl LendingIterator for Permutations {
+    type Item = [usize];
+
+    fn next(&mut self) -> Option<&[usize]> {
+        if self.position >= self.total {
+            return None;
+        }
+        if self.position == 0 {
+            self.order
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, val)| *val = i);
+        } else {
+            self.step();
+        }
+        self.position += 1;
+
+        Some(&self.order)
+    }
+
+    fn nth(&mut self, n: u128) -> Option<&[usize]> {
+        let rank = self.position.saturating_add(n);
+        if rank >= self.total {
+            self.position = self.total;
+            return None;
+        }
+        self.unrank(rank);
+        self.position = rank + 1;
+
+        Some(&self.order)
+    }
+
+    fn total(&self) -> u128 {
+        self.total
+    }
+
+    fn remaining(&self) -> u128 {
+        self.total - self.position
+    }
+}
+
+#[allow(dead_code)]
+fn factorial(n: usize) -> u128 {
+    (1..=n as u128).product()
+}
+
+/// Every way to split a count into a number of parts, each 0 or more, in lexicographic order
+/// The same as every multiset of that size from the parts, counting how many of each it has
+/// Ex: 2 into 3 parts
+/// 0 0 2, 0 1 1, 0 2 0, 1 0 1, 1 1 0, 2 0 0
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub(crate) struct Compositions {
+    count: usize,
+    parts: Vec<usize>,
+    total: u128,
+    /// Index of the next item to lend out
+    position: u128,
+}
+
+#[allow(dead_code)]
+impl Compositions {
+    pub(crate) fn new(count: usize, parts: usize) -> Self {
+        Self {
+            count,
+            parts: vec![0; parts],
+            total: Self::ways(count, parts),
+            position: 0,
+        }
+    }
+
+    /// Number of ways to split the count into the parts
+    fn ways(count: usize, parts: usize) -> u128 {
+        match parts {
+            0 => (count == 0) as u128,
+            _ => binomial(count + parts - 1, parts - 1),
+        }
+    }
+
+    /// Returns the index of the split in the order
+    pub(crate) fn rank(&self, split: &[usize]) -> u128 {
+        let parts = split.len();
+        let mut left = self.count;
+        let mut rank = 0;
+        for (i, &val) in split.iter().enumerate().take(parts.saturating_sub(1)) {
+            // Every split with less in this part comes first
+            for lower in 0..val {
+                rank += Self::ways(left - lower, parts - i - 1);
+            }
+            left -= val;
+        }
+
+        rank
+    }
+
+    /// Writes the split at the index into the buffer
+    fn unrank(&mut self, mut rank: u128) {
+        let parts = self.parts.len();
+        let mut left = self.count;
+        for i in 0..parts - 1 {
+            let mut val = 0;
+            loop {
+                let skip = Self::ways(left - val, parts - i - 1);
+                if rank < skip {
+                    break;
+                }
+                rank -= skip;
+                val += 1;
+            }
+            self.parts[i] = val;
+            left -= val;
+        }
+        self.parts[parts - 1] = left;
+    }
+
+    /// Moves the buffer on to the next split
+    /// Ex: 1 0 2 0 -> 1 1 0 1
+    /// Add one to the last part before something that isn't 0 (ignoring the last part),
+    /// then put the rest of what came after it in the last part
+    fn step(&mut self) {
+        let last = self.parts.len() - 1;
+        let mut tail = self.parts[last];
+        for i in (0..last).rev() {
+            if tail > 0 {
+                self.parts[i] += 1;
+                self.parts[i + 1..].fill(0);
+                self.parts[last] = tail - 1;
+                return;
+            }
+            tail += self.parts[i];
+        }
+
+        panic!("Stepped past the last split");
+    }
+}
+
+impl LendingIterator for Compositions {
+    type Item = [usize];
+
+    fn next(&mut self) -> Option<&[usize]> {
+        if self.position >= self.total {
+            return None;
+        }
+        if self.position == 0 {
+            if let Some(last) = self.parts.last_mut() {
+                *last = self.count;
+            }
+        } else {
+            self.step();
+        }
+        self.position += 1;
+
+        Some(&self.parts)
+    }
+
+    fn nth(&mut self, n: u128) -> Option<&[usize]> {
+        let rank = self.position.saturating_add(n);
+        if rank >= self.total {
+            self.position = self.total;
+            return None;
+        }
+        if !self.parts.is_empty() {
+            self.unrank(rank);
+        }
+        self.position = rank + 1;
+
+        Some(&self.parts)
+    }
+
+    fn total(&self) -> u128 {
+        self.total
+    }
+
+    fn remaining(&self) -> u128 {
+        self.total - self.position
+    }
+}
+
+/// Every subset of n things, in Gray code order: each subset differs from the one before
+/// it by exactly one thing going in or out
+/// Ex: 3
+/// {}, {0}, {0 1}, {1}, {1 2}, {0 1 2}, {0 2}, {2}
+#[derive(Debug, Clone)]
+pub(crate) struct GrayCode {
+    subset: Vec<bool>,
+    /// Which thing went in or out to get the last subset lent out
+    flipped: Option<usize>,
+    total: u128,
+    /// Index of the next item to lend out
+    position: u128,
+}
+
+impl GrayCode {
+    /// Panics past n = 127, where the number of subsets no longer fits in a u128
+    pub(crate) fn new(n: usize) -> Self {
+        assert!(n < 128, "2^{} doesn't fit in a u128", n);
+        Self {
+            subset: vec![false; n],
+            flipped: None,
+            total: 1 << n,
+            position: 0,
+        }
+    }
+
+    /// Which thing went in or out to get the last subset lent out by next
+    /// None for the first subset, or one jumped to with nth
+    pub(crate) fn flipped(&self) -> Option<usize> {
+        self.flipped
+    }
+
+    /// Returns the index of the subset in the order
+    #[allow(dead_code)]
+    pub(crate) fn rank(&self, subset: &[bool]) -> u128 {
+        // Undo gray = rank ^ (rank >> 1) one bit at a time from the top
+        let mut rank = 0;
+        let mut bit = false;
+        for idx in (0..subset.len()).rev() {
+            bit ^= subset[idx];
+            rank |= (bit as u128) << idx;
+        }
+
+        rank
+    }
+}
+
+impl LendingIterator for GrayCode {
+    type Item = [bool];
+
+    fn next(&mut self) -> Option<&[bool]> {
+        if self.position >= self.total {
+            return None;
+        }
+        if self.position == 0 {
+            self.subset.fill(false);
+            self.flipped = None;
+        } else {
+            // The lowest set bit of the index is the one that changes
+            let idx = self.position.trailing_zeros() as usize;
+            self.subset[idx] = !self.subset[idx];
+            self.flipped = Some(idx);
+        }
+        self.position += 1;
+
+        Some(&self.subset)
+    }
+
+    fn nth(&mut self, n: u128) -> Option<&[bool]> {
+        let rank = self.position.saturating_add(n);
+        if rank >= self.total {
+            self.position = self.total;
+            return None;
+        }
+        let gray = rank ^ (rank >> 1);
+        for (idx, member) in self.subset.iter_mut().enumerate() {
+            *member = gray & (1 << idx) != 0;
+        }
+        self.flipped = None;
+        self.position = rank + 1;
+
+        Some(&self.subset)
+    }
+
+    fn total(&self) -> u128 {
+        self.total
+    }
+
+    fn remaining(&self) -> u128 {
+        self.total - self.position
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Collects every item, then checks nth and rank agree with where each one came out
+    fn check_order<I, T>(mut iter: I, rank: impl Fn(&I, &I::Item) -> u128) -> Vec<Vec<T>>
+    where
+        I: LendingIterator<Item = [T]> + Clone,
+        T: Clone + PartialEq + std::fmt::Debug,
+    {
+        let fresh = iter.clone();
+        let total = iter.total();
+        let mut items: Vec<Vec<T>> = Vec::new();
+        while let Some(item) = iter.next() {
+            items.push(item.to_vec());
+            assert_eq!(iter.remaining(), total - items.len() as u128);
+        }
+        assert_eq!(items.len() as u128, total);
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+        assert!(iter.next().is_none());
+
+        for (idx, item) in items.iter().enumerate() {
+            assert_eq!(rank(&fresh, item), idx as u128);
+            assert_eq!(fresh.clone().nth(idx as u128), Some(item.as_slice()));
+        }
+
+        // Carries on from wherever nth left off
+        let mut skipping = fresh.clone();
+        if total > 2 {
+            assert_eq!(skipping.nth(1), Some(items[1].as_slice()));
+            assert_eq!(skipping.next(), Some(items[2].as_slice()));
+        }
+        assert_eq!(skipping.nth(total), None);
+        assert_eq!(skipping.remaining(), 0);
+
+        items
+    }
+
+    #[test]
+    fn test_combinations() {
+        let items = check_order(Combinations::new(4, 2), Combinations::rank);
+        assert_eq!(
+            items,
+            vec![
+                vec![0, 1],
+                vec![0, 2],
+                vec![0, 3],
+                vec![1, 2],
+                vec![1, 3],
+                vec![2, 3],
+            ]
+        );
+
+        let items = check_order(Combinations::new(6, 3), Combinations::rank);
+        assert_eq!(items.len(), 20);
+        let at = |item: &[usize]| items.iter().position(|i| i == item).unwrap();
+        assert_eq!(at(&[1, 2, 5]) + 1, at(&[1, 3, 4]));
+        assert_eq!(at(&[2, 4, 5]) + 1, at(&[3, 4, 5]));
+
+        assert_eq!(
+            check_order(Combinations::new(3, 0), Combinations::rank),
+            vec![vec![]]
+        );
+        assert_eq!(
+            check_order(Combinations::new(2, 3), Combinations::rank).len(),
+            0
+        );
+
+        let mut big = Combinations::new(100, 50);
+        assert_eq!(big.total(), 100_891_344_545_564_193_334_812_497_256);
+        assert_eq!(big.size_hint(), (usize::MAX, None));
+        let last: Vec<usize> = big.nth(big.total() - 1).unwrap().to_vec();
+        assert_eq!(last, (50..100).collect::<Vec<usize>>());
+        assert_eq!(big.rank(&last), big.total() - 1);
+    }
+
+    #[test]
+    fn test_permutations() {
+        let items = check_order(Permutations::new(3), Permutations::rank);
+        assert_eq!(
+            items,
+            vec![
+                vec![0, 1, 2],
+                vec![0, 2, 1],
+                vec![1, 0, 2],
+                vec![1, 2, 0],
+                vec![2, 0, 1],
+                vec![2, 1, 0],
+            ]
+        );
+        assert_eq!(
+            check_order(Permutations::new(5), Permutations::rank).len(),
+            120
+        );
+        assert_eq!(
+            check_order(Permutations::new(0), Permutations::rank),
+            vec![vec![]]
+        );
+
+        let mut big = Permutations::new(34);
+        let last: Vec<usize> = big.nth(big.total() - 1).unwrap().to_vec();
+        assert_eq!(last, (0..34).rev().collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_compositions() {
+        let items = check_order(Compositions::new(2, 3), Compositions::rank);
+        assert_eq!(
+            items,
+            vec![
+                vec![0, 0, 2],
+                vec![0, 1, 1],
+                vec![0, 2, 0],
+                vec![1, 0, 1],
+                vec![1, 1, 0],
+                vec![2, 0, 0],
+            ]
+        );
+
+        // Pressing one of three buttons once
+        let items = check_order(Compositions::new(1, 3), Compositions::rank);
+        assert_eq!(items, vec![vec![0, 0, 1], vec![0, 1, 0], vec![1, 0, 0]]);
+
+        let items = check_order(Compositions::new(5, 4), Compositions::rank);
+        assert_eq!(items.len(), 56);
+        assert!(items.iter().all(|split| split.iter().sum::<usize>() == 5));
+
+        assert_eq!(
+            check_order(Compositions::new(4, 1), Compositions::rank),
+            vec![vec![4]]
+        );
+        assert_eq!(
+            check_order(Compositions::new(0, 0), Compositions::rank),
+            vec![vec![]]
+        );
+        assert_eq!(
+            check_order(Compositions::new(3, 0), Compositions::rank).len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_gray_code() {
+        let mut order = GrayCode::new(3);
+        let mut flips: Vec<Option<usize>> = Vec::new();
+        while order.next().is_some() {
+            flips.push(order.flipped());
+        }
+        assert_eq!(
+            flips,
+            vec![
+                None,
+                Some(0),
+                Some(1),
+                Some(0),
+                Some(2),
+                Some(0),
+                Some(1),
+                Some(0)
+            ]
+        );
+
+        let items = check_order(GrayCode::new(3), GrayCode::rank);
+        let members: Vec<Vec<usize>> = items
+            .iter()
+            .map(|subset| (0..3).filter(|&idx| subset[idx]).collect())
+            .collect();
+        assert_eq!(
+            members,
+            vec![
+                vec![],
+                vec![0],
+                vec![0, 1],
+                vec![1],
+                vec![1, 2],
+                vec![0, 1, 2],
+                vec![0, 2],
+                vec![2],
+            ]
+        );
+
+        // Every subset once, each one thing away from the last
+        let items = check_order(GrayCode::new(6), GrayCode::rank);
+        for pair in items.windows(2) {
+            let changed = pair[0].iter().zip(&pair[1]).filter(|(a, b)| a != b).count();
+            assert_eq!(changed, 1);
+        }
+        let mut masks: Vec<usize> = items
+            .iter()
+            .map(|subset| (0..6).filter(|&idx| subset[idx]).map(|idx| 1 << idx).sum())
+            .collect();
+        masks.sort_unstable();
+        assert_eq!(masks, (0..64).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_binomial() {
+        assert_eq!(binomial(5, 2), 10);
+        assert_eq!(binomial(5, 0), 1);
+        assert_eq!(binomial(5, 6), 0);
+        assert_eq!(binomial(0, 0), 1);
+        assert_eq!(binomial(62, 31), 465_428_353_255_261_088);
+        assert_eq!(binomial(130, 1), 130);
+        // Multiplying before dividing would overflow on the way to this
+        assert_eq!(
+            binomial(130, 65),
+            95_067_625_827_960_698_145_584_333_020_095_113_100
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::ops::RangeInclusive;
+
+/// Squashes coordinates along one axis down to consecutive cell indices
+///
+/// Every coordinate added gets a cell of its own, and every gap between two added coordinates
+/// gets one cell standing in for all of it, so each cell still covers a known stretch of the
+/// real coordinates
+///
+/// Ex: adding 2, 6, 7 and 9
+///
+/// ```text
+/// real:  2 3 4 5 6 7 8 9
+/// cell:  0 1 1 1 2 3 4 5
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CoordinateCompressor {
+    /// First real coordinate covered by each cell
+    starts: Vec<u64>,
+    /// Last real coordinate covered by each cell
+    ends: Vec<u64>,
+}
+
+impl CoordinateCompressor {
+    pub(crate) fn new(coords: impl IntoIterator<Item = u64>) -> Self {
+        let mut coords: Vec<u64> = coords.into_iter().collect();
+        coords.sort_unstable();
+        coords.dedup();
+
+        let mut compressor = CoordinateCompressor {
+            starts: Vec::with_capacity(coords.len() * 2),
+            ends: Vec::with_capacity(coords.len() * 2),
+        };
+        for (i, &coord) in coords.iter().enumerate() {
+            compressor.push(coord, coord);
+            if let Some(&next) = coords.get(i + 1)
+                && next > coord + 1
+            {
+                // Gap up to the next coordinate
+                compressor.push(coord + 1, next - 1);
+            }
+        }
+
+        compressor
+    }
+
+    fn push(&mut self, start: u64, end: u64) {
+        self.starts.push(start);
+        self.ends.push(end);
+    }
+
+    /// Number of cells
+    pub(crate) fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Returns the cell covering the real coordinate, or None if it is past either end
+    pub(crate) fn compress(&self, coord: u64) -> Option<usize> {
+        let idx = self
+            .starts
+            .partition_point(|&start| start <= coord)
+            .checked_sub(1)?;
+        (coord <= self.ends[idx]).then_some(idx)
+    }
+
+    /// Returns the real coordinates covered by the cell
+    pub(crate) fn decompress(&self, idx: usize) -> RangeInclusive<u64> {
+        self.starts[idx]..=self.ends[idx]
+    }
+
+    /// Number of real coordinates covered by the cell
+    pub(crate) fn width(&self, idx: usize) -> u64 {
+        self.ends[idx] - self.starts[idx] + 1
+    }
+
+    /// Number of real coordinates covered by the cells from one to the other, inclusive
+    pub(crate) fn span(&self, idx1: usize, idx2: usize) -> u64 {
+        self.ends[idx1.max(idx2)] - self.starts[idx1.min(idx2)] + 1
+    }
+}
+
+/// Compressed coordinates on both axes of a plane
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CompressedGrid {
+    pub(crate) x: CoordinateCompressor,
+    pub(crate) y: CoordinateCompressor,
+}
+
+impl CompressedGrid {
+    pub(crate) fn new(points: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let (xs, ys): (Vec<u64>, Vec<u64>) = points.into_iter().unzip();
+        CompressedGrid {
+            x: CoordinateCompressor::new(xs),
+            y: CoordinateCompressor::new(ys),
+        }
+    }
+
+    /// Returns the cell covering the real point, or None if it is off the grid
+    pub(crate) fn compress(&self, (x, y): (u64, u64)) -> Option<(usize, usize)> {
+        Some((self.x.compress(x)?, self.y.compress(y)?))
+    }
+
+    /// Number of real points covered by the cell
+    pub(crate) fn cell_area(&self, (x, y): (usize, usize)) -> u64 {
+        self.x.width(x) * self.y.width(y)
+    }
+
+    /// Number of real points covered by the rectangle of cells between the two cells, inclusive
+    pub(crate) fn area_between(&self, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> u64 {
+        self.x.span(x1, x2) * self.y.span(y1, y2)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gap_cells() {
+        let compressor = CoordinateCompressor::new([9, 2, 7, 6, 2]);
+        assert_eq!(compressor.len(), 6);
+
+        let cells: Vec<usize> = (2..=9).map(|x| compressor.compress(x).unwrap()).collect();
+        assert_eq!(cells, vec![0, 1, 1, 1, 2, 3, 4, 5]);
+        assert_eq!(compressor.compress(1), None);
+        assert_eq!(compressor.compress(10), None);
+
+        assert_eq!(compressor.decompress(1), 3..=5);
+        assert_eq!(compressor.decompress(4), 8..=8);
+        assert_eq!(compressor.decompress(3), 7..=7);
+
+        let widths: Vec<u64> = (0..compressor.len())
+            .map(|idx| compressor.width(idx))
+            .collect();
+        assert_eq!(widths, vec![1, 3, 1, 1, 1, 1]);
+        assert_eq!(widths.iter().sum::<u64>(), 8);
+        assert_eq!(compressor.span(5, 1), 7);
+        assert_eq!(compressor.span(2, 2), 1);
+
+        // Every cell maps back to itself
+        for idx in 0..compressor.len() {
+            for coord in compressor.decompress(idx) {
+                assert_eq!(compressor.compress(coord), Some(idx));
+            }
+        }
+
+        let empty = CoordinateCompressor::new([]);
+        assert_eq!(empty.len(), 0);
+        assert_eq!(empty.compress(0), None);
+    }
+
+    #[test]
+    fn test_grid_areas() {
+        let grid = CompressedGrid::new([(0, 0), (10, 4), (3, 4)]);
+        // x cells: 0, 1..=2, 3, 4..=9, 10 | y cells: 0, 1..=3, 4
+        assert_eq!((grid.x.len(), grid.y.len()), (5, 3));
+        assert_eq!(grid.compress((5, 2)), Some((3, 1)));
+        assert_eq!(grid.compress((11, 2)), None);
+        assert_eq!(grid.cell_area((3, 1)), 18);
+
+        // Cell areas add up to the whole grid
+        let total: u64 = (0..grid.x.len())
+            .flat_map(|x| (0..grid.y.len()).map(move |y| (x, y)))
+            .map(|cell| grid.cell_area(cell))
+            .sum();
+        assert_eq!(total, 11 * 5);
+        assert_eq!(grid.area_between((0, 0), (4, 2)), total);
+        assert_eq!(grid.area_between((2, 2), (1, 1)), 3 * 4);
+    }
+}
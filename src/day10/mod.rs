@@ -1,4 +1,20 @@
-use z3::{Solver, ast::Int};
+use std::fmt::Display;
+use std::ops::Neg;
+#[cfg(feature = "z3")]
+use std::time::Duration;
+
+#[cfg(feature = "z3")]
+use z3::{Optimize, Params, SatResult, ast::Int};
+
+use crate::combinatorics::{Combinations, Compositions, GrayCode, LendingIterator};
+#[cfg(feature = "z3")]
+use crate::settings::setting;
+use crate::settings::{output_path, write_output};
+
+/// How long Z3 gets to find the fewest presses for each machine, unless DAY10_Z3_TIMEOUT_MS
+/// says otherwise, with 0 to wait forever
+#[cfg(feature = "z3")]
+const JOLTAGE_TIMEOUT_MS: u64 = 30_000;
 
 pub(crate) fn solve_day10() {
     // Get the input: List of factory machine information
@@ -7,12 +23,31 @@ pub(crate) fn solve_day10() {
 
     let machines = parse_input(input);
 
+    // Part 2 solver
+    #[cfg(feature = "z3")]
+    let solver = Z3 {
+        timeout: match setting("DAY10_Z3_TIMEOUT_MS", JOLTAGE_TIMEOUT_MS) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis)),
+        },
+        // Branch and bound has no time limit, so only use it when DAY10_FALLBACK=true
+        fallback: setting("DAY10_FALLBACK", false),
+    };
+    #[cfg(not(feature = "z3"))]
+    let solver = BranchAndBound;
+
+    let reports: Vec<MachineReport> = machines
+        .iter()
+        .enumerate()
+        .map(|(idx, machine)| MachineReport::new(idx, machine, &solver))
+        .collect();
+
     // Part 1: Toggle indicator lights
-    let mut indicator_lights_sum: usize = 0;
-    for machine in &machines {
-        let min_buttons = machine.solve_lights();
-        indicator_lights_sum += min_buttons;
-    }
+    let indicator_lights_sum: usize = reports
+        .iter()
+        .filter_map(|report| report.lights.as_ref().ok())
+        .map(Vec::len)
+        .sum();
 
     println!(
         "Indicator lights: Sum of number of hits: {}",
@@ -20,15 +55,83 @@ pub(crate) fn solve_day10() {
     );
 
     // Part 2: Increase Joltage
-    let mut joltage_sum: u64 = 0;
-    for machine in &machines {
-        let min_buttons = machine.solve_joltage();
-        joltage_sum += min_buttons;
-    }
+    let joltage_sum: u64 = reports
+        .iter()
+        .filter_map(|report| report.joltage.as_ref().ok())
+        .map(|presses| presses.iter().sum::<u64>())
+        .sum();
 
     // 20735 too high
     // 20617 - just right!
     println!("Joltage: Sum of number of hits: {}", joltage_sum);
+
+    let unsolved: Vec<&JoltageError> = reports
+        .iter()
+        .filter_map(|report| report.joltage.as_ref().err())
+        .collect();
+    if !unsolved.is_empty() {
+        let infeasible = unsolved
+            .iter()
+            .filter(|err| ***err == JoltageError::Infeasible)
+            .count();
+        let miswired = unsolved
+            .iter()
+            .filter(|err| matches!(err, JoltageError::UnknownCounter { .. }))
+            .count();
+        println!(
+            "Joltage: {} machines unsolved ({} infeasible, {} miswired, {} gave up)",
+            unsolved.len(),
+            infeasible,
+            miswired,
+            unsolved.len() - infeasible - miswired
+        );
+    }
+
+    let fallbacks: Vec<usize> = reports
+        .iter()
+        .filter(|report| report.fallback.is_some())
+        .map(|report| report.idx)
+        .collect();
+    if !fallbacks.is_empty() {
+        println!(
+            "Joltage: {} machines finished by branch and bound after the solver gave up: {:?}",
+            fallbacks.len(),
+            fallbacks
+        );
+    }
+
+    // Anything that went wrong
+    let ok = reports.iter().filter(|report| report.is_ok()).count();
+    println!("{} of {} machines check out", ok, reports.len());
+    for report in reports.iter().filter(|report| !report.is_ok()) {
+        println!("{}", report);
+        print_matrix(&report.machine.get_joltage_matrix());
+    }
+    // Every machine, solved or not
+    if let Some(path) = output_path("day10-report.txt") {
+        let text: Vec<String> = reports.iter().map(MachineReport::to_string).collect();
+        write_output(path, text.join("\n"));
+    }
+}
+
+/// Why the fewest presses for a machine's joltage weren't found
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum JoltageError {
+    /// A button is wired to a counter the machine doesn't have
+    UnknownCounter { button: usize, counter: usize },
+    /// No number of presses reaches the joltage
+    Infeasible,
+    /// Solving needed numbers too big for an i128, or divided by 0
+    Arithmetic(FractionError),
+    /// Z3 gave up before finding the fewest presses, usually by running out of time
+    #[cfg(feature = "z3")]
+    Unknown { reason: Option<String> },
+}
+
+impl From<FractionError> for JoltageError {
+    fn from(err: FractionError) -> Self {
+        JoltageError::Arithmetic(err)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -39,61 +142,6 @@ struct Machine {
 }
 
 impl Machine {
-    /// Return the lowest number of presses of groups of buttons required to
-    /// increase the joltage to the desired amounts
-    fn solve_joltage(&self) -> u64 {
-        // Turn the buttons and desired joltage into a matrix
-        let mat = self.get_joltage_matrix();
-
-        // Set up Z3 solver
-        let solver = Solver::new();
-        let mut vars: Vec<Int> = Vec::with_capacity(mat.cols() - 1);
-        for i in 0..mat.cols() - 1 {
-            vars.push(Int::fresh_const(&format!("v{}", i)));
-        }
-
-        // Constraints
-        // Vars cannot be negative
-        vars.iter().for_each(|var| solver.assert(var.ge(0)));
-
-        // Matrix row:
-        for row in mat.0 {
-            // Add this equation to the solver
-            let lhs = vars
-                .iter()
-                .zip(row.iter())
-                .map(|(var, &coeff)| var * &Int::from(coeff))
-                .reduce(|acc, term| acc + (&term))
-                .unwrap();
-            let rhs = Int::from(row[row.len() - 1]);
-            solver.assert(&lhs.eq(&rhs));
-        }
-
-        // Solve and check solutions
-        let mut min_presses = u64::MAX;
-        for solution in solver.solutions(vars, true) {
-            let solution: Vec<u64> = solution
-                .iter()
-                .map(Int::as_u64)
-                .map(Option::unwrap)
-                .collect();
-            let sum: u64 = solution.iter().sum();
-            if sum < min_presses {
-                min_presses = sum;
-                println!("Solution: {:?}", solution);
-            }
-        }
-
-        if min_presses == u64::MAX {
-            println!("ERROR: No solution!");
-            let mat = self.get_joltage_matrix();
-            print_matrix(&mat);
-        }
-
-        println!("Entry can be solved in {} presses", min_presses);
-        min_presses
-    }
-
     /// Creates a matrix of the button presses and the desired joltage
     /// [.##.] (3) (1,3) (2) (2,3) (0,2) (0,1) {3,5,4,7}
     ///         a    b    c    d     e     f
@@ -121,7 +169,44 @@ impl Machine {
             mat.push(row);
         }
 
-        Matrix(mat)
+        Matrix::from(mat)
+    }
+
+    /// Returns the lights after pressing each of the buttons once, starting from all off
+    fn press_lights(&self, buttons: &[usize]) -> Vec<bool> {
+        let mut lights = vec![false; self.lights.len()];
+        for &button in buttons {
+            for &light in &self.buttons[button] {
+                lights[light] = !lights[light];
+            }
+        }
+
+        lights
+    }
+
+    /// Returns the joltage after pressing each button that many times, starting from 0
+    /// Counters the machine doesn't have are left out
+    fn press_joltage(&self, presses: &[u64]) -> Vec<usize> {
+        let mut joltage = vec![0; self.joltage.len()];
+        for (button, &count) in self.buttons.iter().zip(presses) {
+            for &counter in button {
+                if let Some(joltage) = joltage.get_mut(counter) {
+                    *joltage += count as usize;
+                }
+            }
+        }
+
+        joltage
+    }
+
+    /// Checks every button is only wired to counters the machine has
+    fn check_counters(&self) -> Result<(), JoltageError> {
+        for (button, wiring) in self.buttons.iter().enumerate() {
+            if let Some(&counter) = wiring.iter().find(|&&c| c >= self.joltage.len()) {
+                return Err(JoltageError::UnknownCounter { button, counter });
+            }
+        }
+        Ok(())
     }
 
     /// Return the lowest number of presses of groups of buttons required to
@@ -135,7 +220,8 @@ impl Machine {
         // Start with the max of the joltage numbers, the answer will never be less
         let mut presses = *self.joltage.iter().max().unwrap();
         loop {
-            if self._check_joltage_counters_brute_force(presses) {
+            if self._can_reach_joltage_in(presses) {
+                println!("Min number found: {}", presses);
                 count = presses;
                 break;
             }
@@ -146,20 +232,20 @@ impl Machine {
     }
 
     /// Check if the joltage levels can be achieved by the number of presses
-    fn _check_joltage_counters_brute_force(&self, presses: usize) -> bool {
-        for button_sets in _SelectNCountIter::_new(self.buttons.len(), presses) {
-            // Index of each button_sets corresponds to index of each button to press,
-            // value corresponds to how many times to press it
-            let mut joltage_test = vec![0; self.joltage.len()];
-            // Each iteration is +1 button press
-            for button_set_to_press in button_sets {
-                for &button in &self.buttons[button_set_to_press] {
-                    joltage_test[button] += 1;
+    /// Tries every way of sharing the presses out between the buttons
+    fn _can_reach_joltage_in(&self, presses: usize) -> bool {
+        let mut joltage_test = vec![0; self.joltage.len()];
+        let mut splits = Compositions::new(presses, self.buttons.len());
+        // Each value of the split is how many times to press that button
+        while let Some(split) = splits.next() {
+            joltage_test.fill(0);
+            for (button, &count) in self.buttons.iter().zip(split) {
+                for &counter in button {
+                    joltage_test[counter] += count;
                 }
             }
 
             if joltage_test == self.joltage {
-                println!("Min number found: {}", presses);
                 return true;
             }
         }
@@ -167,15 +253,27 @@ impl Machine {
         false
     }
 
+    /// Returns the fewest buttons to press to turn on the desired indicator lights
+    /// Pressing a button twice undoes it, so each button is pressed at most once
+    fn solve_lights(&self) -> Result<Vec<usize>, LightsError> {
+        let system = LightsSystem::new(self)?;
+        let presses = system.min_presses();
+
+        Ok((0..self.buttons.len())
+            .filter(|&button| presses & (1 << button) != 0)
+            .collect())
+    }
+
     /// Counts the lowest number of presses of groups of its buttons required to
     /// turn on the desired indicator lights
-    fn solve_lights(&self) -> usize {
+    /// Tries every combination of buttons, so is exponential in the number of buttons
+    fn _solve_lights_brute_force(&self) -> usize {
         let mut count: usize = 0;
 
         // Keep checking all possible combinations of number of buttons pressed
         let n = self.buttons.len();
         for r in 1..=n {
-            if self.check_ncr_button_groups(n, r) {
+            if self._can_set_lights_with(r) {
                 count = r;
                 break;
             }
@@ -185,16 +283,18 @@ impl Machine {
         count
     }
 
-    /// Checks whether or not a selection of pressing n button groups
+    /// Checks whether or not a selection of pressing r button groups
     /// can turn on the indicator lights to the desired pattern
-    fn check_ncr_button_groups(&self, n: usize, r: usize) -> bool {
+    fn _can_set_lights_with(&self, r: usize) -> bool {
         // Ex: Pick 4
         // 1: 1 2 3 4
         // 2: 1(2 3 4) 2(3 4) 3(4)
         // 3: 1(2(3 4))
-        for button_sets in NChooseRIter::new(n, r) {
-            let mut lights_test = vec![false; self.lights.len()];
-            for button_set in button_sets {
+        let mut lights_test = vec![false; self.lights.len()];
+        let mut combinations = Combinations::new(self.buttons.len(), r);
+        while let Some(button_sets) = combinations.next() {
+            lights_test.fill(false);
+            for &button_set in button_sets {
                 for &button in &self.buttons[button_set] {
                     lights_test[button] = !lights_test[button];
                 }
@@ -208,303 +308,943 @@ impl Machine {
     }
 }
 
+/// Why a machine's indicator lights can't be set
 #[derive(Debug, PartialEq, Eq, Clone)]
-struct Matrix(Vec<Vec<i32>>);
-
-impl Matrix {
-    /// Returns the number of rows
-    fn _rows(&self) -> usize {
-        self.0.len()
-    }
+enum LightsError {
+    /// A button is wired to a light the machine doesn't have
+    UnknownLight { button: usize, light: usize },
+    /// More lights than fit in a u64 bitmask, or so many buttons that every
+    /// combination of them can't be counted in one
+    TooLarge { lights: usize, buttons: usize },
+    /// Adding up the equations for these lights gives 0 = 1:
+    /// every button toggles an even number of them, but an odd number need turning on
+    Inconsistent { lights: Vec<usize> },
+}
 
-    /// Returns the number of columns
-    fn cols(&self) -> usize {
-        self.0[0].len()
-    }
+/// Equation for one light over GF(2), where adding is xor
+/// Ex: light 2 of [.##.] (3) (1,3) (2) (2,3) (0,2) (0,1)
+///                        a    b    c    d     e     f
+///     c + d + e = 1
+#[derive(Debug, Clone, Copy)]
+struct LightsEquation {
+    /// Buttons toggling the light, bit per button
+    buttons: u64,
+    /// Whether the light needs turning on
+    on: bool,
+    /// Original lights added together to make this equation, bit per light
+    lights: u64,
+}
 
-    /// Performs Gauss-Jordan elimination to reduce a matrix
-    fn _gauss_jordan_elimination(&mut self) {
-        let rows = self.0.len();
+/// Every way of pressing a machine's buttons which sets its indicator lights
+/// Found by Gauss-Jordan elimination over GF(2)
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct LightsSystem {
+    /// One set of buttons which sets the lights, bit per button
+    particular: u64,
+    /// Sets of buttons which leave the lights as they are, bit per button
+    /// Toggling any combination of them on top of the particular set also sets the lights
+    null_space: Vec<u64>,
+}
 
-        // Current index of the column which is being transformed into a 1
-        let mut pivot_row: usize;
-        let mut pivot_col: usize = 0;
+impl LightsSystem {
+    fn new(machine: &Machine) -> Result<Self, LightsError> {
+        let lights = machine.lights.len();
+        let buttons = machine.buttons.len();
+        if lights > 64 || buttons >= 64 {
+            return Err(LightsError::TooLarge { lights, buttons });
+        }
 
-        let mut rescale_needed: bool = false;
+        let mut equations: Vec<LightsEquation> = (0..lights)
+            .map(|light| LightsEquation {
+                buttons: 0,
+                on: machine.lights[light],
+                lights: 1 << light,
+            })
+            .collect();
+        for (button, wiring) in machine.buttons.iter().enumerate() {
+            for &light in wiring {
+                let equation = equations
+                    .get_mut(light)
+                    .ok_or(LightsError::UnknownLight { button, light })?;
+                equation.buttons |= 1 << button;
+            }
+        }
 
-        for r in 0..rows {
-            // 1. Find the next available pivot column
-            // Check each column for the presence of a nonzero value
-            (pivot_row, pivot_col) = match self._next_available_pivot_column(r, pivot_col) {
-                Some((row, col)) => (row, col),
-                None => {
-                    // Unable to find another pivot value. Ending
-                    return;
-                }
+        // Reduce so each pivot button only appears in its own equation
+        let mut pivots: Vec<usize> = Vec::with_capacity(lights.min(buttons));
+        for button in 0..buttons {
+            let rank = pivots.len();
+            let bit = 1 << button;
+            let Some(pivot_row) = (rank..lights).find(|&r| equations[r].buttons & bit != 0) else {
+                // Free button
+                continue;
             };
+            equations.swap(rank, pivot_row);
+            let pivot = equations[rank];
+            for (r, equation) in equations.iter_mut().enumerate() {
+                if r != rank && equation.buttons & bit != 0 {
+                    equation.buttons ^= pivot.buttons;
+                    equation.on ^= pivot.on;
+                    equation.lights ^= pivot.lights;
+                }
+            }
+            pivots.push(button);
+        }
 
-            // 2. Swap a row with said column index present into the current row
-            self.0.swap(r, pivot_row);
-            // Now the row with the pivot column is at index r
+        // Anything past the pivots has no buttons left, so must not need any lights on
+        if let Some(equation) = equations[pivots.len()..].iter().find(|eq| eq.on) {
+            return Err(LightsError::Inconsistent {
+                lights: (0..lights)
+                    .filter(|&light| equation.lights & (1 << light) != 0)
+                    .collect(),
+            });
+        }
 
-            // 3. Scale the row if needed so the pivot column entry is equal to 1
-            let rescale_result = Self::_try_rescale_row(&mut self.0[r]);
+        // Leave the free buttons unpressed and read off the pivot buttons
+        let particular = pivots
+            .iter()
+            .zip(&equations)
+            .filter(|(_, equation)| equation.on)
+            .fold(0, |acc, (&button, _)| acc | (1 << button));
+
+        // Pressing a free button means also pressing every pivot button whose equation has it
+        let null_space = (0..buttons)
+            .filter(|button| !pivots.contains(button))
+            .map(|free| {
+                pivots
+                    .iter()
+                    .zip(&equations)
+                    .filter(|(_, equation)| equation.buttons & (1 << free) != 0)
+                    .fold(1 << free, |acc, (&button, _)| acc | (1 << button))
+            })
+            .collect();
 
-            // 4. Add/Subtract this row from any following rows which also contain our pivot column
-            // Only attempt this subtraction if the previous rescale attempt passed
-            // so the pivot column value will be 1
-            if rescale_result.is_ok() {
-                self._subtract_out_pivot(r, pivot_col);
-            }
+        Ok(Self {
+            particular,
+            null_space,
+        })
+    }
 
-            // Attempt to rescale any previously failed row
-            if rescale_needed {
-                rescale_needed = self._try_rescale_and_subtract_first_n_rows(r).is_err();
+    /// Returns the set of buttons which sets the lights with the fewest presses
+    /// Walks every combination of the null space in Gray code order,
+    /// so each step only toggles one null space vector
+    fn min_presses(&self) -> u64 {
+        let mut current = self.particular;
+        let mut best = current;
+        let mut combinations = GrayCode::new(self.null_space.len());
+        while combinations.next().is_some() {
+            if let Some(flipped) = combinations.flipped() {
+                current ^= self.null_space[flipped];
             }
-
-            // Try a full rescale on next attempt if this row failed
-            if rescale_result.is_err() {
-                rescale_needed = true;
+            if current.count_ones() < best.count_ones() {
+                best = current;
             }
         }
+
+        best
     }
+}
 
-    /// Returns the row and column index of the next available pivot column
-    /// beginning from the provided indices
-    fn _next_available_pivot_column(
-        &self,
-        start_row: usize,
-        start_col: usize,
-    ) -> Option<(usize, usize)> {
-        let rows = self.0.len();
-        let cols = self.0[0].len();
+/// Solves the joltage with the solver
+/// If the solver falls back, branch and bound finishes anything it gives up on,
+/// and the error it gave up with is returned alongside
+fn solve_joltage(
+    solver: &impl Solver,
+    machine: &Machine,
+) -> (Result<Vec<u64>, JoltageError>, Option<JoltageError>) {
+    let result = solver.solve_joltage(machine);
+    #[cfg(feature = "z3")]
+    if solver.falls_back()
+        && let Err(err @ JoltageError::Unknown { .. }) = result
+    {
+        return (BranchAndBound.solve_joltage(machine), Some(err));
+    }
+    (result, None)
+}
 
-        // Last column is the result, do not count it
-        for check_col in start_col..cols - 1 {
-            for check_row in start_row..rows {
-                if self.0[check_row][check_col] != 0 {
-                    return Some((check_row, check_col));
+/// What elimination says about a machine's joltage equations
+#[derive(Debug)]
+struct JoltageEquations {
+    /// Number of independent joltage equations
+    rank: usize,
+    /// Number of buttons whose presses can be picked freely, the rest follow from them
+    degrees_of_freedom: usize,
+    /// Groups of counters whose equations contradict each other
+    conflicts: Vec<Vec<usize>>,
+}
+
+impl JoltageEquations {
+    fn new(machine: &Machine) -> Result<Self, FractionError> {
+        let mat = machine.get_joltage_matrix();
+        let reduced = mat.reduce()?;
+
+        // Combinations of counter equations which cancel out every button
+        // but leave a nonzero joltage mean 0 = joltage
+        // A single counter on its own only does that when it is unreachable
+        let counters = mat.0.len();
+        let buttons = mat.cols() - 1;
+        let mut conflicts: Vec<Vec<usize>> = Vec::new();
+        if !reduced.is_consistent() {
+            let mut transposed: Vec<Vec<Fraction>> = (0..buttons)
+                .map(|button| mat.0.iter().map(|row| row[button]).collect())
+                .collect();
+            transposed
+                .iter_mut()
+                .for_each(|row| row.push(Fraction::ZERO));
+            for combination in Matrix(transposed).reduce()?.null_space() {
+                let joltage = combination
+                    .iter()
+                    .zip(&mat.0)
+                    .try_fold(Fraction::ZERO, |acc, (&times, row)| {
+                        acc.checked_add(times.checked_mul(row[buttons])?)
+                    })?;
+                let group: Vec<usize> = (0..counters)
+                    .filter(|&counter| !combination[counter].is_zero())
+                    .collect();
+                if !joltage.is_zero() && group.len() > 1 {
+                    conflicts.push(group);
                 }
             }
         }
 
-        None
+        Ok(Self {
+            rank: reduced.rank(),
+            degrees_of_freedom: reduced.free_columns().len(),
+            conflicts,
+        })
     }
+}
 
-    /// Goes through the first so many rows and attempts to scale them
-    /// so that the first nonzero value in that row is 1
-    ///
-    /// Return: Result whether all rows are properly rescaled
-    fn _try_rescale_and_subtract_first_n_rows(&mut self, n: usize) -> Result<(), ()> {
-        let mut res = Ok(());
-        for r in 0..n {
-            // Check if this row needs a rescale
-            if let Some((pivot_col, val)) = Self::_get_first_nonzero_value(&self.0[r])
-                && val != 1
-            {
-                if Self::_try_rescale_row(&mut self.0[r]).is_ok() {
-                    // Row rescaled! Now subtract with it
-                    self._subtract_out_pivot(r, pivot_col);
-                } else {
-                    res = Err(());
-                }
-            };
+/// Everything worked out about one machine
+#[derive(Debug)]
+struct MachineReport<'a> {
+    /// Position of the machine in the input
+    idx: usize,
+    machine: &'a Machine,
+    /// What elimination says about the joltage equations, unless the fractions got too big
+    equations: Result<JoltageEquations, FractionError>,
+    /// Counters needing joltage which no button adds to
+    unreachable: Vec<usize>,
+    /// Buttons to press to set the lights
+    lights: Result<Vec<usize>, LightsError>,
+    /// Number of times to press each button to reach the joltage
+    joltage: Result<Vec<u64>, JoltageError>,
+    /// Why the solver gave up on the joltage, leaving branch and bound to finish it
+    fallback: Option<JoltageError>,
+}
+
+impl<'a> MachineReport<'a> {
+    fn new(idx: usize, machine: &'a Machine, solver: &impl Solver) -> Self {
+        let unreachable: Vec<usize> = (0..machine.joltage.len())
+            .filter(|&counter| {
+                machine.joltage[counter] != 0
+                    && !machine
+                        .buttons
+                        .iter()
+                        .any(|button| button.contains(&counter))
+            })
+            .collect();
+
+        let lights = machine.solve_lights();
+        let (joltage, fallback) = solve_joltage(solver, machine);
+
+        Self {
+            idx,
+            machine,
+            equations: JoltageEquations::new(machine),
+            unreachable,
+            lights,
+            joltage,
+            fallback,
         }
+    }
 
-        res
+    /// Whether pressing the buttons for the lights really gives the lights, None if unsolved
+    fn lights_check(&self) -> Option<bool> {
+        let buttons = self.lights.as_ref().ok()?;
+        Some(self.machine.press_lights(buttons) == self.machine.lights)
     }
 
-    /// Tries to rescale the row so that the first nonzero value is 1
-    /// Row of all zeros is properly scaled and returns Ok(())
-    ///
-    /// Return: Result whether the row was properly rescaled
-    fn _try_rescale_row(row: &mut Vec<i32>) -> Result<(), ()> {
-        if let Some((_idx, scale_val)) = Self::_get_first_nonzero_value(row) {
-            match scale_val {
-                1 => Ok(()), // As expected
-                -1 => {
-                    // Multiply all values of the row by -1
-                    row.iter_mut().for_each(|val| *val *= -1);
-                    Ok(())
-                }
-                0 => {
-                    panic!("Rows with all 0s should fail the option check");
-                }
-                _ => {
-                    // Uhhhh we're only using integers so this could get bad
-                    if row.iter().any(|val| val % scale_val != 0) {
-                        Err(())
-                    } else {
-                        row.iter_mut().for_each(|val| *val /= scale_val);
-                        Ok(())
-                    }
-                }
-            }
-        } else {
-            // All 0s - this is properly scaled
-            Ok(())
-        }
+    /// Whether pressing the buttons for the joltage really gives the joltage, None if unsolved
+    fn joltage_check(&self) -> Option<bool> {
+        let presses = self.joltage.as_ref().ok()?;
+        Some(self.machine.press_joltage(presses) == self.machine.joltage)
     }
 
-    /// Subtracts out a pivot column using the equation in the specified row
-    fn _subtract_out_pivot(&mut self, pivot_row: usize, pivot_col: usize) {
-        let rows = self.0.len();
-        let cols = self.0[0].len();
+    /// Whether both parts are solved and check out
+    fn is_ok(&self) -> bool {
+        self.lights_check() == Some(true) && self.joltage_check() == Some(true)
+    }
+}
 
-        // This row must be properly scaled
-        assert_eq!(self.0[pivot_row][pivot_col], 1);
+impl Display for MachineReport<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let check = |check: Option<bool>| match check {
+            Some(true) => "checks out",
+            _ => "DOES NOT CHECK OUT",
+        };
+
+        writeln!(f, "Machine {}: {}", self.idx, self.machine)?;
+        writeln!(
+            f,
+            "{} lights, {} buttons, {} counters",
+            self.machine.lights.len(),
+            self.machine.buttons.len(),
+            self.machine.joltage.len()
+        )?;
+        match &self.equations {
+            Ok(equations) => writeln!(
+                f,
+                "Rank {}, {} degrees of freedom",
+                equations.rank, equations.degrees_of_freedom
+            )?,
+            Err(err) => writeln!(f, "Equations: {:?}", err)?,
+        }
+        if !self.unreachable.is_empty() {
+            writeln!(f, "Unreachable counters: {:?}", self.unreachable)?;
+        }
+        if let Ok(equations) = &self.equations
+            && !equations.conflicts.is_empty()
+        {
+            writeln!(f, "Conflicting counters: {:?}", equations.conflicts)?;
+        }
 
-        for target_row in 0..rows {
-            let pivot_val = self.0[target_row][pivot_col];
-            // println!("Pivot val: {}", pivot_val);
-            if target_row != pivot_row && pivot_val != 0 {
-                for c in 0..cols {
-                    self.0[target_row][c] -= pivot_val * self.0[pivot_row][c];
-                }
-            }
+        match &self.lights {
+            Ok(buttons) => writeln!(
+                f,
+                "Lights: press {:?} ({})",
+                buttons,
+                check(self.lights_check())
+            )?,
+            Err(err) => writeln!(f, "Lights: {:?}", err)?,
+        }
+        if let Some(err) = &self.fallback {
+            writeln!(
+                f,
+                "Joltage: gave up with {:?}, trying branch and bound",
+                err
+            )?;
+        }
+        match &self.joltage {
+            Ok(presses) => writeln!(
+                f,
+                "Joltage: press {:?} = {} presses ({})",
+                presses,
+                presses.iter().sum::<u64>(),
+                check(self.joltage_check())
+            ),
+            Err(err) => writeln!(f, "Joltage: {:?}", err),
         }
     }
+}
+
+/// Finds how many times to press each of a machine's buttons to increase the joltage to
+/// the desired amounts with the lowest total number of presses
+trait Solver {
+    fn solve_joltage(&self, machine: &Machine) -> Result<Vec<u64>, JoltageError>;
+
+    /// Whether branch and bound should finish anything this solver gives up on
+    #[cfg(feature = "z3")]
+    fn falls_back(&self) -> bool {
+        false
+    }
+}
+
+/// Hands the joltage equations to Z3's optimizer
+/// Z3 stops looking after the timeout, if there is one
+#[cfg(feature = "z3")]
+struct Z3 {
+    timeout: Option<Duration>,
+    /// Whether branch and bound takes over when Z3 gives up
+    fallback: bool,
+}
+
+#[cfg(feature = "z3")]
+impl Solver for Z3 {
+    fn falls_back(&self) -> bool {
+        self.fallback
+    }
+
+    fn solve_joltage(&self, machine: &Machine) -> Result<Vec<u64>, JoltageError> {
+        machine.check_counters()?;
+        // Z3 can't add up an empty list of presses
+        if machine.buttons.is_empty() {
+            return match machine.joltage.iter().all(|&joltage| joltage == 0) {
+                true => Ok(Vec::new()),
+                false => Err(JoltageError::Infeasible),
+            };
+        }
+
+        // Turn the buttons and desired joltage into a matrix
+        let mat = machine.get_joltage_matrix();
+
+        // Set up Z3 optimizer
+        let optimizer = Optimize::new();
+        if let Some(timeout) = self.timeout {
+            let mut params = Params::new();
+            let millis = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+            params.set_u32("timeout", millis);
+            optimizer.set_params(&params);
+        }
+        let mut vars: Vec<Int> = Vec::with_capacity(mat.cols() - 1);
+        for i in 0..mat.cols() - 1 {
+            vars.push(Int::fresh_const(&format!("v{}", i)));
+        }
+
+        // Constraints
+        // Vars cannot be negative
+        vars.iter().for_each(|var| optimizer.assert(&var.ge(0)));
+
+        // Matrix row:
+        // Nothing is reduced yet so every value is a whole number
+        for row in mat.0 {
+            // Add this equation to the solver
+            let lhs = vars
+                .iter()
+                .zip(row.iter())
+                .map(|(var, coeff)| var * &Int::from(coeff.num as i64))
+                .reduce(|acc, term| acc + (&term))
+                .unwrap();
+            let rhs = Int::from(row[row.len() - 1].num as i64);
+            optimizer.assert(&lhs.eq(&rhs));
+        }
 
-    /// Returns the first nonzero value in the list along with its index
-    fn _get_first_nonzero_value(row: &Vec<i32>) -> Option<(usize, i32)> {
-        for (idx, &val) in row.iter().enumerate() {
-            if val != 0 {
-                return Some((idx, val));
+        // Fewest presses in total
+        optimizer.minimize(&Int::add(&vars));
+
+        match optimizer.check(&[]) {
+            SatResult::Sat => {
+                let model = optimizer.get_model().expect("Sat results have a model");
+                let presses: Vec<u64> = vars
+                    .iter()
+                    .map(|var| model.eval(var, true).as_ref().and_then(Int::as_u64))
+                    .map(Option::unwrap)
+                    .collect();
+                // println!("Solution: {:?}", presses);
+                Ok(presses)
             }
+            SatResult::Unsat => Err(JoltageError::Infeasible),
+            SatResult::Unknown => Err(JoltageError::Unknown {
+                reason: optimizer.get_reason_unknown(),
+            }),
         }
+    }
+}
+
+/// Pure Rust solver: Gauss-Jordan elimination over exact fractions leaves a few free buttons,
+/// then branch and bound tries their press counts within what the joltage allows
+struct BranchAndBound;
 
-        None
+impl Solver for BranchAndBound {
+    fn solve_joltage(&self, machine: &Machine) -> Result<Vec<u64>, JoltageError> {
+        JoltageSystem::new(machine)?
+            .min_presses()
+            .ok_or(JoltageError::Infeasible)
     }
 }
 
-struct _SelectNCountIter {
-    n: usize,
-    /// Vec length of count, where each of them correspond to the index of each selection
-    /// Each value will range from 0..=n
-    next: Option<Vec<usize>>,
+/// Equation for a pivot button once the joltage matrix is reduced,
+/// scaled up so every value is an integer:
+/// scale * presses = rhs - sum(coeffs[f] * free presses[f])
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PivotEquation {
+    button: usize,
+    scale: i128,
+    rhs: i128,
+    /// One per free button
+    coeffs: Vec<i128>,
 }
 
-impl _SelectNCountIter {
-    fn _new(n: usize, count: usize) -> Self {
-        let next: Vec<usize> = vec![0; count];
-        Self {
-            n,
-            next: Some(next),
-        }
-    }
-}
-
-impl Iterator for _SelectNCountIter {
-    type Item = Vec<usize>;
-
-    /// Ex: 5 choose 3
-    /// 0 0 0
-    /// 0 0 1
-    /// ...
-    /// 0 0 5
-    /// 0 1 0 Overflow!
-    /// 0 1 1
-    ///  ...
-    /// 0 1 5
-    /// 0 2 0 Overflow!
-    fn next(&mut self) -> Option<Self::Item> {
-        // Increment the lowest index
-        // If its new value is greater than the count, increment the next one and reset these
-        // End when all values are n
-        let n = self.n;
-        let rtn = self.next.clone();
-        if let Some(next) = &mut self.next {
-            for val in next {
-                *val += 1;
-                if *val >= n {
-                    *val = 0;
-                } else {
-                    break;
+/// Joltage equations reduced to the free buttons, which decide every other button's presses
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct JoltageSystem {
+    buttons: usize,
+    /// Buttons not picked as a pivot by the elimination
+    free: Vec<usize>,
+    pivots: Vec<PivotEquation>,
+    /// Most times each button can be pressed before overshooting one of its counters
+    bounds: Vec<i128>,
+    /// Total presses, scaled up by cost_scale: cost_scale * total = cost + sum(free_costs[f] * free presses[f])
+    cost: i128,
+    free_costs: Vec<i128>,
+    cost_scale: i128,
+}
+
+impl JoltageSystem {
+    fn new(machine: &Machine) -> Result<Self, JoltageError> {
+        // The matrix only has rows for the counters the machine has
+        machine.check_counters()?;
+        let reduced = machine.get_joltage_matrix().reduce()?;
+        // Leftover rows have no buttons, so 0 = joltage
+        if !reduced.is_consistent() {
+            return Err(JoltageError::Infeasible);
+        }
+
+        // Each button is a column + the output joltage
+        let buttons = reduced.mat.cols() - 1;
+        let free = reduced.free_columns();
+
+        // A button can't be pressed more than the lowest joltage it adds to
+        let bounds: Vec<i128> = machine
+            .buttons
+            .iter()
+            .map(|counters| {
+                counters
+                    .iter()
+                    .filter_map(|&counter| machine.joltage.get(counter))
+                    .min()
+                    .map_or(0, |&joltage| joltage as i128)
+            })
+            .collect();
+
+        // Each press of a free button moves along its null space vector,
+        // so changes the total by the sum of the vector
+        // total = sum(pivot rhs) + sum(sum(null space[f]) * free presses[f])
+        let free_costs: Vec<Fraction> = reduced
+            .null_space()
+            .iter()
+            .map(|vector| {
+                vector
+                    .iter()
+                    .try_fold(Fraction::ZERO, |acc, &val| acc.checked_add(val))
+            })
+            .collect::<Result<_, _>>()?;
+        let mut cost = Fraction::ZERO;
+        let mut pivots: Vec<PivotEquation> = Vec::with_capacity(reduced.rank());
+        for (row, &button) in reduced.mat.0.iter().zip(reduced.pivot_columns()) {
+            let coeffs: Vec<Fraction> = free.iter().map(|&f| row[f]).collect();
+            cost = cost.checked_add(row[buttons])?;
+
+            let scale = common_denominator(row[buttons], &coeffs)?;
+            pivots.push(PivotEquation {
+                button,
+                scale,
+                rhs: scale_up(row[buttons], scale)?,
+                coeffs: coeffs
+                    .iter()
+                    .map(|&c| scale_up(c, scale))
+                    .collect::<Result<_, _>>()?,
+            });
+        }
+
+        let cost_scale = common_denominator(cost, &free_costs)?;
+        let system = Self {
+            buttons,
+            pivots,
+            bounds,
+            cost: scale_up(cost, cost_scale)?,
+            free_costs: free_costs
+                .iter()
+                .map(|&c| scale_up(c, cost_scale))
+                .collect::<Result<_, _>>()?,
+            cost_scale,
+            free,
+        };
+        system.check_range()?;
+        Ok(system)
+    }
+
+    /// Checks branch and bound's plain i128 sums can't overflow:
+    /// each one stays within its start plus the biggest swing of every free button,
+    /// and no total can be more than every button pressed as often as it can be
+    fn check_range(&self) -> Result<(), FractionError> {
+        let reach = |start: i128, coeffs: &[i128]| {
+            self.free
+                .iter()
+                .zip(coeffs)
+                .try_fold(start.checked_abs()?, |acc, (&button, &coeff)| {
+                    acc.checked_add(coeff.checked_abs()?.checked_mul(self.bounds[button])?)
+                })
+        };
+        let most_presses = self
+            .bounds
+            .iter()
+            .try_fold(0i128, |acc, &bound| acc.checked_add(bound));
+
+        let in_range = self.pivots.iter().all(|pivot| {
+            reach(pivot.rhs, &pivot.coeffs).is_some()
+                && pivot.scale.checked_mul(self.bounds[pivot.button]).is_some()
+        }) && reach(self.cost, &self.free_costs).is_some()
+            && most_presses
+                .and_then(|total| total.checked_mul(self.cost_scale))
+                .is_some();
+        match in_range {
+            true => Ok(()),
+            false => Err(FractionError::Overflow),
+        }
+    }
+
+    /// Returns the presses for each button with the lowest total, or None if nothing works
+    fn min_presses(&self) -> Option<Vec<u64>> {
+        let mut best: Option<(u64, Vec<u64>)> = None;
+        let mut free_presses: Vec<i128> = vec![0; self.free.len()];
+        let mut remaining: Vec<i128> = self.pivots.iter().map(|pivot| pivot.rhs).collect();
+        self.branch(0, &mut free_presses, &mut remaining, self.cost, &mut best);
+
+        best.map(|(_, presses)| presses)
+    }
+
+    /// Tries every press count for the free button at the depth, then the ones after it
+    /// Cuts off branches where a pivot button can no longer land between 0 and its bound,
+    /// or where the total can no longer beat the best found so far
+    fn branch(
+        &self,
+        depth: usize,
+        free_presses: &mut Vec<i128>,
+        remaining: &mut Vec<i128>,
+        cost: i128,
+        best: &mut Option<(u64, Vec<u64>)>,
+    ) {
+        if depth == self.free.len() {
+            let mut presses: Vec<u64> = vec![0; self.buttons];
+            for (&button, &count) in self.free.iter().zip(free_presses.iter()) {
+                presses[button] = count as u64;
+            }
+            for (pivot, &rhs) in self.pivots.iter().zip(remaining.iter()) {
+                if rhs < 0 || rhs % pivot.scale != 0 {
+                    return;
                 }
+                presses[pivot.button] = (rhs / pivot.scale) as u64;
             }
+
+            let total = presses.iter().sum();
+            if best
+                .as_ref()
+                .is_none_or(|(best_total, _)| total < *best_total)
+            {
+                *best = Some((total, presses));
+            }
+            return;
+        }
+
+        // Lowest total still possible from the free buttons not yet picked
+        let rest = &self.free[depth..];
+        let lowest_cost = cost
+            + rest
+                .iter()
+                .zip(&self.free_costs[depth..])
+                .map(|(&button, &c)| (c * self.bounds[button]).min(0))
+                .sum::<i128>();
+        if let Some((best_total, _)) = best
+            && lowest_cost >= *best_total as i128 * self.cost_scale
+        {
+            return;
         }
 
-        // Check end condition
-        if let Some(next) = &self.next {
-            if next.iter().sum::<usize>() == 0 {
-                self.next = None;
+        // Every pivot must still be able to land between 0 and its bound
+        for (pivot, &rhs) in self.pivots.iter().zip(remaining.iter()) {
+            let (mut low, mut high) = (rhs, rhs);
+            for (&button, &coeff) in rest.iter().zip(&pivot.coeffs[depth..]) {
+                let swing = coeff * self.bounds[button];
+                low -= swing.max(0);
+                high -= swing.min(0);
+            }
+            if high < 0 || low > pivot.scale * self.bounds[pivot.button] {
+                return;
             }
         }
 
-        rtn
+        for count in 0..=self.bounds[self.free[depth]] {
+            free_presses[depth] = count;
+            for (pivot, rhs) in self.pivots.iter().zip(remaining.iter_mut()) {
+                *rhs -= pivot.coeffs[depth] * count;
+            }
+            self.branch(
+                depth + 1,
+                free_presses,
+                remaining,
+                cost + self.free_costs[depth] * count,
+                best,
+            );
+            for (pivot, rhs) in self.pivots.iter().zip(remaining.iter_mut()) {
+                *rhs += pivot.coeffs[depth] * count;
+            }
+        }
     }
 }
 
-struct NChooseRIter {
-    n: usize,
-    r: usize,
-    /// Length r, values ranging from 0..n, corresponding to which indices to select
-    next: Option<Vec<usize>>,
+/// Why exact fraction arithmetic has no answer
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum FractionError {
+    /// A numerator or denominator doesn't fit in an i128
+    Overflow,
+    /// A fraction with a 0 denominator, like from dividing by 0
+    ZeroDenominator,
 }
 
-impl NChooseRIter {
-    fn new(n: usize, r: usize) -> Self {
-        let mut next: Vec<usize> = Vec::with_capacity(n);
-        for i in 0..r {
-            next.push(i);
+/// Exact rational number, kept in lowest terms with a positive denominator
+/// Arithmetic is checked and never leaves i128::MIN behind, so negating can't overflow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fraction {
+    num: i128,
+    den: i128,
+}
+
+impl Fraction {
+    const ZERO: Fraction = Fraction { num: 0, den: 1 };
+    const ONE: Fraction = Fraction { num: 1, den: 1 };
+
+    fn new(num: i128, den: i128) -> Result<Self, FractionError> {
+        if den == 0 {
+            return Err(FractionError::ZeroDenominator);
         }
-        Self {
-            n,
-            r,
-            next: Some(next),
+        if num == i128::MIN || den == i128::MIN {
+            return Err(FractionError::Overflow);
         }
+        let divisor = gcd(num, den) * den.signum();
+        Ok(Self {
+            num: num / divisor,
+            den: den / divisor,
+        })
     }
 
-    /// Returns the number of positions that are at the end of the list
-    /// Ex: for the 6 choose 3 list [0 1 2 3 4 5]
-    ///     if your positions are:   * * *        => 0 move the last one (idx 2)
-    ///     if your positions are:   *       * *  => 2 move the first one (idx 0)
-    ///                                * * *           then move the others back
-    ///     if your positions are:   *   *     *  => 1 move the middle one (idx 1)
-    ///     if your positions are:         * * *  => 3 you're done
-    fn how_many_are_at_the_end(&self) -> usize {
-        let mut count: usize = 0;
-        if let Some(next) = &self.next {
-            for &pos in next.iter().rev() {
-                if pos == self.n - 1 - count {
-                    count += 1;
-                } else {
-                    break;
-                }
-            }
+    /// Builds the fraction from checked products, which are None if they overflowed
+    fn from_checked(num: Option<i128>, den: Option<i128>) -> Result<Self, FractionError> {
+        Fraction::new(
+            num.ok_or(FractionError::Overflow)?,
+            den.ok_or(FractionError::Overflow)?,
+        )
+    }
+
+    fn checked_add(self, rhs: Self) -> Result<Self, FractionError> {
+        let num = self
+            .num
+            .checked_mul(rhs.den)
+            .zip(rhs.num.checked_mul(self.den))
+            .and_then(|(lhs, rhs)| lhs.checked_add(rhs));
+        Fraction::from_checked(num, self.den.checked_mul(rhs.den))
+    }
+
+    fn checked_sub(self, rhs: Self) -> Result<Self, FractionError> {
+        self.checked_add(-rhs)
+    }
+
+    fn checked_mul(self, rhs: Self) -> Result<Self, FractionError> {
+        Fraction::from_checked(self.num.checked_mul(rhs.num), self.den.checked_mul(rhs.den))
+    }
+
+    /// Dividing by 0 is a ZeroDenominator error
+    fn checked_div(self, rhs: Self) -> Result<Self, FractionError> {
+        Fraction::from_checked(self.num.checked_mul(rhs.den), self.den.checked_mul(rhs.num))
+    }
+
+    fn is_zero(&self) -> bool {
+        self.num == 0
+    }
+}
+
+impl Display for Fraction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
         } else {
-            count = self.r;
+            write!(f, "{}/{}", self.num, self.den)
         }
+    }
+}
 
-        count
+impl From<i128> for Fraction {
+    fn from(value: i128) -> Self {
+        Self { num: value, den: 1 }
     }
 }
 
-impl Iterator for NChooseRIter {
-    type Item = Vec<usize>;
+impl Neg for Fraction {
+    type Output = Fraction;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // Take your vector of n indices
-        // Start at 0..n
-        // Increment the last one until it gets to n-1
-        // Then increment the second to last one till it gets to n-2... and so on
-        let rtn = self.next.clone();
-        let at_end = self.how_many_are_at_the_end();
-        if let Some(next) = &mut self.next {
-            if at_end == self.r {
-                // End condition
-                self.next = None
-            } else {
-                // Move the last index that's not at the end to the end
-                let mut start_pos = next[self.r - 1 - at_end] + 1;
-                for i in self.r - 1 - at_end..self.r {
-                    next[i] = start_pos;
-                    start_pos += 1;
+    fn neg(self) -> Self::Output {
+        Fraction {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+}
+
+/// Greatest common divisor, always positive unless both are 0
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Lowest common multiple of two positive numbers, None if it doesn't fit in an i128
+fn lcm(a: i128, b: i128) -> Option<i128> {
+    (a / gcd(a, b)).checked_mul(b)
+}
+
+/// Lowest number which is a multiple of the denominator of the value and every coefficient
+fn common_denominator(value: Fraction, coeffs: &[Fraction]) -> Result<i128, FractionError> {
+    coeffs
+        .iter()
+        .try_fold(value.den, |acc, c| lcm(acc, c.den))
+        .ok_or(FractionError::Overflow)
+}
+
+/// Numerator of the fraction times the scale, which is a multiple of its denominator
+fn scale_up(value: Fraction, scale: i128) -> Result<i128, FractionError> {
+    Ok(value.checked_mul(Fraction::from(scale))?.num)
+}
+
+/// Matrix of exact fractions for a system of equations
+/// One row per equation, with the right hand side in the last column
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Matrix(Vec<Vec<Fraction>>);
+
+impl Matrix {
+    /// Returns the number of rows
+    fn _rows(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the number of columns
+    fn cols(&self) -> usize {
+        self.0[0].len()
+    }
+
+    /// Performs Gauss-Jordan elimination to reduce a matrix to reduced row echelon form:
+    /// each pivot is 1 and the only nonzero value in its column
+    /// The last column is the result, it never holds a pivot
+    ///
+    /// Return: Pivot column of each leading row, any rows after them are 0 bar the result
+    /// or the error if the fractions got too big
+    fn gauss_jordan_elimination(&mut self) -> Result<Vec<usize>, FractionError> {
+        let rows = self.0.len();
+        let cols = self.cols();
+
+        let mut pivots: Vec<usize> = Vec::with_capacity(rows.min(cols - 1));
+        for col in 0..cols - 1 {
+            let r = pivots.len();
+            // 1. Find a row with this column present
+            let Some(pivot_row) = (r..rows).find(|&row| !self.0[row][col].is_zero()) else {
+                continue;
+            };
+
+            // 2. Swap it into the current row
+            self.0.swap(r, pivot_row);
+
+            // 3. Scale the row so the pivot is 1
+            let pivot = self.0[r][col];
+            for val in self.0[r].iter_mut() {
+                *val = val.checked_div(pivot)?;
+            }
+
+            // 4. Subtract it out of every other row
+            for target_row in 0..rows {
+                let pivot_val = self.0[target_row][col];
+                if target_row != r && !pivot_val.is_zero() {
+                    for c in 0..cols {
+                        self.0[target_row][c] = self.0[target_row][c]
+                            .checked_sub(pivot_val.checked_mul(self.0[r][c])?)?;
+                    }
                 }
             }
+
+            pivots.push(col);
         }
 
-        rtn
+        Ok(pivots)
+    }
+
+    /// Returns a reduced copy of the matrix
+    fn reduce(&self) -> Result<ReducedMatrix, FractionError> {
+        let mut mat = self.clone();
+        let pivots = mat.gauss_jordan_elimination()?;
+        Ok(ReducedMatrix { mat, pivots })
+    }
+}
+
+impl From<Vec<Vec<i32>>> for Matrix {
+    fn from(value: Vec<Vec<i32>>) -> Self {
+        Matrix(
+            value
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|val| Fraction::from(val as i128))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Matrix in reduced row echelon form, with the column of each pivot
+/// Ex: Example 1 from scratch.txt
+///  a  b  c  d  e   f  =
+/// [1, 0, 0, 1, 0, -1, 2]
+/// [0, 1, 0, 0, 0,  1, 5]
+/// [0, 0, 1, 1, 0, -1, 1]
+/// [0, 0, 0, 0, 1,  1, 3]
+/// Pivots: a b c e, free: d f
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct ReducedMatrix {
+    mat: Matrix,
+    pivots: Vec<usize>,
+}
+
+impl ReducedMatrix {
+    /// Number of independent equations
+    fn rank(&self) -> usize {
+        self.pivots.len()
+    }
+
+    /// Variable each independent equation solves for
+    fn pivot_columns(&self) -> &[usize] {
+        &self.pivots
+    }
+
+    /// Variables which can take any value, the pivot variables follow from them
+    fn free_columns(&self) -> Vec<usize> {
+        (0..self.mat.cols() - 1)
+            .filter(|col| !self.pivots.contains(col))
+            .collect()
+    }
+
+    /// Whether the equations have a solution: no row reduces to 0 = nonzero
+    fn is_consistent(&self) -> bool {
+        let result = self.mat.cols() - 1;
+        self.mat.0[self.rank()..]
+            .iter()
+            .all(|row| row[result].is_zero())
+    }
+
+    /// Basis of the solutions with every result 0, one vector per free variable
+    /// Setting the free variable to 1 and the others to 0 means each pivot variable is
+    /// minus its row's value in the free column
+    /// Ex: d from the example above: [-1, 0, -1, 1, 0, 0]
+    fn null_space(&self) -> Vec<Vec<Fraction>> {
+        let vars = self.mat.cols() - 1;
+        self.free_columns()
+            .into_iter()
+            .map(|free| {
+                let mut vector = vec![Fraction::ZERO; vars];
+                vector[free] = Fraction::ONE;
+                for (row, &pivot) in self.mat.0.iter().zip(&self.pivots) {
+                    vector[pivot] = -row[free];
+                }
+                vector
+            })
+            .collect()
+    }
+}
+
+/// Writes the machine back out the way it's written in the input
+impl Display for Machine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lights: String = self
+            .lights
+            .iter()
+            .map(|&on| if on { '#' } else { '.' })
+            .collect();
+        write!(f, "[{}]", lights)?;
+        for button in &self.buttons {
+            let button: Vec<String> = button.iter().map(usize::to_string).collect();
+            write!(f, " ({})", button.join(","))?;
+        }
+        let joltage: Vec<String> = self.joltage.iter().map(usize::to_string).collect();
+        write!(f, " {{{}}}", joltage.join(","))
     }
 }
 
@@ -562,7 +1302,8 @@ fn parse_input(input: &str) -> Vec<Machine> {
 fn print_matrix(Matrix(mat): &Matrix) {
     println!("Matrix {} x {}:", mat.len(), mat[0].len());
     for row in mat {
-        println!("{:?}", row);
+        let row: Vec<String> = row.iter().map(Fraction::to_string).collect();
+        println!("[{}]", row.join(", "));
     }
 }
 
@@ -571,77 +1312,306 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_ncr_iter() {
-        assert_eq!(
-            NChooseRIter {
-                n: 6,
-                r: 3,
-                next: Some(vec![1, 2, 5])
+    fn test_solve_lights() {
+        let machines = parse_input(include_str!("day10-test.txt"));
+        let buttons: Vec<Vec<usize>> = machines
+            .iter()
+            .map(|machine| machine.solve_lights().unwrap())
+            .collect();
+        assert_eq!(buttons, vec![vec![1, 3], vec![2, 3, 4], vec![1, 2]]);
+
+        // (0,2) + (0,1) toggles 1 and 2
+        let system = LightsSystem::new(&machines[0]).unwrap();
+        assert_eq!(system.null_space.len(), 2);
+        for &vector in &system.null_space {
+            let mut lights = vec![false; machines[0].lights.len()];
+            for button in (0..machines[0].buttons.len()).filter(|b| vector & (1 << b) != 0) {
+                for &light in &machines[0].buttons[button] {
+                    lights[light] = !lights[light];
+                }
             }
-            .how_many_are_at_the_end(),
-            1
+            assert_eq!(lights, vec![false; machines[0].lights.len()]);
+        }
+
+        // Matches trying every combination
+        for machine in parse_input(include_str!("day10-input.txt")) {
+            assert_eq!(
+                machine.solve_lights().unwrap().len(),
+                machine._solve_lights_brute_force()
+            );
+        }
+
+        // Lights 0 and 1 are always toggled together
+        let machine = Machine::from("[#.#] (0,1) (2) (1,0,2) {1,1,1}");
+        assert_eq!(
+            machine.solve_lights(),
+            Err(LightsError::Inconsistent { lights: vec![0, 1] })
+        );
+        let machine = Machine::from("[#.] (0,2) {1,1}");
+        assert_eq!(
+            machine.solve_lights(),
+            Err(LightsError::UnknownLight {
+                button: 0,
+                light: 2
+            })
         );
+        let machine = Machine::from("[.] (0) {0}");
+        assert_eq!(machine.solve_lights(), Ok(vec![]));
+
+        // Every button is free, so there would be 2^64 combinations of them
+        let machine = Machine {
+            lights: vec![false],
+            buttons: vec![vec![]; 64],
+            joltage: vec![0],
+        };
+        assert_eq!(
+            machine.solve_lights(),
+            Err(LightsError::TooLarge {
+                lights: 1,
+                buttons: 64
+            })
+        );
+    }
 
+    /// Checks pressing each button that many times gives the machine's joltage
+    fn check_presses(machine: &Machine, presses: &[u64]) {
+        assert_eq!(machine.press_joltage(presses), machine.joltage);
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let machines = parse_input(include_str!("day10-test.txt"));
+        let mut totals: Vec<u64> = Vec::new();
+        for machine in &machines {
+            let presses = BranchAndBound.solve_joltage(machine).unwrap();
+            check_presses(machine, &presses);
+            totals.push(presses.iter().sum());
+        }
+        assert_eq!(totals, vec![10, 12, 11]);
+
+        let system = JoltageSystem::new(&machines[0]).unwrap();
+        assert_eq!(system.free.len(), 2);
+        assert_eq!(system.bounds, vec![7, 5, 4, 4, 3, 3]);
+
+        let mut joltage_sum: u64 = 0;
+        for machine in parse_input(include_str!("day10-input.txt")) {
+            let presses = BranchAndBound.solve_joltage(&machine).unwrap();
+            check_presses(&machine, &presses);
+            joltage_sum += presses.iter().sum::<u64>();
+        }
+        assert_eq!(joltage_sum, 20617);
+
+        // Counter 0 gets both buttons, counter 1 just the first
+        let machine = Machine::from("[..] (0,1) (0) {1,2}");
         assert_eq!(
-            NChooseRIter {
-                n: 6,
-                r: 3,
-                next: Some(vec![2, 4, 5])
-            }
-            .how_many_are_at_the_end(),
-            2
+            BranchAndBound.solve_joltage(&machine),
+            Err(JoltageError::Infeasible)
+        );
+        // Fractions from the elimination that only work out for an even press count
+        let machine = Machine::from("[...] (0,1) (1,2) (0,2) {1,1,1}");
+        assert_eq!(
+            BranchAndBound.solve_joltage(&machine),
+            Err(JoltageError::Infeasible)
         );
 
+        assert_eq!(Fraction::new(6, -4), Ok(Fraction { num: -3, den: 2 }));
+        let third = Fraction::new(1, 3).unwrap();
         assert_eq!(
-            NChooseRIter {
-                n: 6,
-                r: 3,
-                next: Some(vec![3, 4, 5])
-            }
-            .how_many_are_at_the_end(),
-            3
+            third.checked_add(Fraction::new(1, 6).unwrap()),
+            Fraction::new(1, 2)
+        );
+        assert_eq!(
+            Fraction::new(1, 2)
+                .unwrap()
+                .checked_div(Fraction::new(-1, 4).unwrap()),
+            Ok(Fraction::from(-2))
         );
 
+        // Too big or divided by 0 is an error rather than a panic
+        let huge = Fraction::from(i128::MAX);
+        assert_eq!(huge.checked_add(huge), Err(FractionError::Overflow));
+        assert_eq!(huge.checked_sub(-huge), Err(FractionError::Overflow));
+        assert_eq!(huge.checked_mul(third), Fraction::new(i128::MAX, 3));
+        assert_eq!(Fraction::new(1, 0), Err(FractionError::ZeroDenominator));
         assert_eq!(
-            NChooseRIter {
-                n: 6,
-                r: 3,
-                next: None
-            }
-            .how_many_are_at_the_end(),
-            3
+            third.checked_div(Fraction::ZERO),
+            Err(FractionError::ZeroDenominator)
         );
+        let mat = Matrix(vec![vec![third, Fraction::ONE], vec![huge, Fraction::ONE]]);
+        assert_eq!(mat.reduce(), Err(FractionError::Overflow));
+    }
 
-        let ncr42 = NChooseRIter::new(4, 2);
-        assert_eq!(ncr42.how_many_are_at_the_end(), 0);
+    #[cfg(feature = "z3")]
+    #[test]
+    fn test_solvers_agree() {
+        let z3 = Z3 {
+            timeout: None,
+            fallback: false,
+        };
+        for machine in parse_input(include_str!("day10-input.txt")) {
+            let native = BranchAndBound.solve_joltage(&machine).unwrap();
+            let optimized = z3.solve_joltage(&machine).unwrap();
+            check_presses(&machine, &optimized);
+            assert_eq!(
+                native.iter().sum::<u64>(),
+                optimized.iter().sum::<u64>(),
+                "{:?}",
+                machine
+            );
+        }
+    }
 
-        let ncr42_expected = vec![
-            vec![0, 1],
-            vec![0, 2],
-            vec![0, 3],
-            vec![1, 2],
-            vec![1, 3],
-            vec![2, 3],
-        ];
+    #[test]
+    fn test_no_buttons() {
+        let machine = Machine {
+            lights: vec![false, false],
+            buttons: Vec::new(),
+            joltage: vec![0, 0],
+        };
+        assert_eq!(BranchAndBound.solve_joltage(&machine), Ok(vec![]));
+        #[cfg(feature = "z3")]
+        assert_eq!(
+            Z3 {
+                timeout: None,
+                fallback: false,
+            }
+            .solve_joltage(&machine),
+            Ok(vec![])
+        );
 
-        for (idx, item) in NChooseRIter::new(4, 2).enumerate() {
-            assert_eq!(ncr42_expected[idx], item);
-        }
+        let machine = Machine {
+            joltage: vec![0, 3],
+            ..machine
+        };
+        assert_eq!(
+            BranchAndBound.solve_joltage(&machine),
+            Err(JoltageError::Infeasible)
+        );
+        #[cfg(feature = "z3")]
+        assert_eq!(
+            Z3 {
+                timeout: None,
+                fallback: false,
+            }
+            .solve_joltage(&machine),
+            Err(JoltageError::Infeasible)
+        );
     }
 
+    #[cfg(feature = "z3")]
     #[test]
-    fn test_selectncount_iter() {
-        let select1of3iter = _SelectNCountIter::_new(3, 1);
-        let s13_expected = vec![vec![0], vec![1], vec![2]];
+    fn test_z3_timeout() {
+        // A millisecond isn't long enough for most machines,
+        // but anything Z3 does finish still has to be the fewest presses
+        let z3 = Z3 {
+            timeout: Some(Duration::from_millis(1)),
+            fallback: false,
+        };
+        let falls_back = Z3 {
+            fallback: true,
+            ..z3
+        };
+        for machine in parse_input(include_str!("day10-input.txt")).iter().take(20) {
+            match z3.solve_joltage(machine) {
+                Ok(presses) => {
+                    check_presses(machine, &presses);
+                    let native = BranchAndBound.solve_joltage(machine).unwrap();
+                    assert_eq!(presses.iter().sum::<u64>(), native.iter().sum::<u64>());
+                }
+                Err(err) => assert!(matches!(err, JoltageError::Unknown { .. }), "{:?}", err),
+            }
+            // Only falls back to branch and bound when asked to
+            let (presses, fallback) = solve_joltage(&z3, machine);
+            assert!(fallback.is_none());
+            if let Err(err) = presses {
+                assert!(matches!(err, JoltageError::Unknown { .. }), "{:?}", err);
+            }
+            let (presses, fallback) = solve_joltage(&falls_back, machine);
+            check_presses(machine, &presses.unwrap());
+            assert!(fallback.is_none_or(|err| matches!(err, JoltageError::Unknown { .. })));
+        }
+    }
 
-        for (idx, item) in select1of3iter.enumerate() {
-            assert_eq!(s13_expected[idx], item);
+    #[test]
+    fn test_machine_report() {
+        let input = include_str!("day10-test.txt");
+        let machines = parse_input(input);
+        for (machine, line) in machines.iter().zip(input.lines()) {
+            assert_eq!(machine.to_string(), line.trim());
         }
+
+        let report = MachineReport::new(0, &machines[0], &BranchAndBound);
+        let equations = report.equations.as_ref().unwrap();
+        assert_eq!((equations.rank, equations.degrees_of_freedom), (4, 2));
+        assert!(report.unreachable.is_empty() && equations.conflicts.is_empty());
+        assert_eq!(report.lights, Ok(vec![1, 3]));
+        assert_eq!(
+            report.joltage.as_ref().map(|p| p.iter().sum::<u64>()),
+            Ok(10)
+        );
+        assert!(report.is_ok() && report.fallback.is_none());
+        let text = report.to_string();
+        assert!(text.starts_with("Machine 0: [.##.] (3) (1,3) (2) (2,3) (0,2) (0,1) {3,5,4,7}\n"));
+        assert!(text.contains("Rank 4, 2 degrees of freedom\n"));
+        assert!(text.contains("Lights: press [1, 3] (checks out)\n"));
+        assert!(text.contains(" = 10 presses (checks out)\n"));
+
+        // Counters 0 and 1 only get the first button, but want different joltages
+        let machine = Machine::from("[..#] (0,1) (2) {1,2,3}");
+        let report = MachineReport::new(1, &machine, &BranchAndBound);
+        let equations = report.equations.as_ref().unwrap();
+        assert_eq!((equations.rank, equations.degrees_of_freedom), (2, 0));
+        assert_eq!(equations.conflicts, vec![vec![0, 1]]);
+        assert!(report.unreachable.is_empty());
+        assert_eq!(report.joltage, Err(JoltageError::Infeasible));
+        assert_eq!(report.lights, Ok(vec![1]));
+        assert_eq!(report.lights_check(), Some(true));
+        assert_eq!(report.joltage_check(), None);
+        assert!(!report.is_ok());
+
+        // Nothing adds to counter 1
+        let machine = Machine::from("[.#] (0) {1,1}");
+        let report = MachineReport::new(1, &machine, &BranchAndBound);
+        assert_eq!(report.unreachable, vec![1]);
+        assert!(report.equations.as_ref().unwrap().conflicts.is_empty());
+        assert_eq!(
+            report.lights,
+            Err(LightsError::Inconsistent { lights: vec![1] })
+        );
+        assert!(report.to_string().contains("Unreachable counters: [1]\n"));
+
+        // Solvable over fractions, but not in whole presses
+        let machine = Machine::from("[..] (0,1) (0) {1,2}");
+        let report = MachineReport::new(1, &machine, &BranchAndBound);
+        assert!(report.unreachable.is_empty());
+        assert!(report.equations.as_ref().unwrap().conflicts.is_empty());
+        assert_eq!(report.joltage, Err(JoltageError::Infeasible));
+
+        // The first button is wired to a counter and light 2, which the machine doesn't have
+        let machine = Machine::from("[#.] (0,2) (1) {1,1}");
+        let report = MachineReport::new(1, &machine, &BranchAndBound);
+        assert_eq!(
+            report.joltage,
+            Err(JoltageError::UnknownCounter {
+                button: 0,
+                counter: 2
+            })
+        );
+        assert_eq!(
+            report.lights,
+            Err(LightsError::UnknownLight {
+                button: 0,
+                light: 2
+            })
+        );
+        assert_eq!(report.joltage_check(), None);
+        assert!(!report.is_ok());
+        assert_eq!(machine.press_joltage(&[1, 1]), vec![1, 1]);
     }
 
     #[test]
     fn test_gauss_jordan_elimination() {
-        let mut m1 = Matrix(vec![
+        let mut m1 = Matrix::from(vec![
             vec![1, 1, 1, 0, 0, 0, 0, 28],
             vec![0, 0, 1, 0, 0, 1, 0, 36],
             vec![1, 1, 0, 0, 1, 0, 1, 163],
@@ -651,11 +1621,11 @@ mod test {
             vec![1, 1, 1, 0, 0, 0, 1, 172],
             vec![1, 1, 0, 0, 1, 0, 0, 19],
         ]);
-        m1._gauss_jordan_elimination();
+        m1.gauss_jordan_elimination().unwrap();
 
         assert_eq!(
             m1,
-            Matrix(vec![
+            Matrix::from(vec![
                 vec![1, 0, 0, 1, 0, 0, 0, 0],
                 vec![0, 1, 0, -1, 0, 0, 0, 8],
                 vec![0, 0, 1, 0, 0, 0, 0, 20],
@@ -668,7 +1638,7 @@ mod test {
         );
         println!("Matrix 1 passed!\n");
 
-        let mut m2 = Matrix(vec![
+        let mut m2 = Matrix::from(vec![
             vec![0, 1, 1, 0, 0, 1, 13],
             vec![0, 0, 1, 1, 1, 1, 43],
             vec![1, 1, 0, 0, 1, 0, 33],
@@ -677,10 +1647,10 @@ mod test {
             vec![1, 1, 0, 1, 0, 1, 41],
         ]);
         print_matrix(&m2);
-        m2._gauss_jordan_elimination();
+        m2.gauss_jordan_elimination().unwrap();
         print_matrix(&m2);
 
-        let m2_expected = Matrix(vec![
+        let m2_expected = Matrix::from(vec![
             vec![1, 0, 0, 0, 0, 0, 15],
             vec![0, 1, 0, 0, 0, 0, 4],
             vec![0, 0, 1, 0, 0, 0, 7],
@@ -692,15 +1662,15 @@ mod test {
         assert_eq!(m2, m2_expected);
         println!("Matrix 2 passed!");
 
-        let mut m3 = Matrix(vec![
+        let mut m3 = Matrix::from(vec![
             vec![0, 0, 0, 0, 1, 1, 3],
             vec![0, 1, 0, 0, 0, 1, 5],
             vec![0, 0, 1, 1, 1, 0, 4],
             vec![1, 1, 0, 1, 0, 0, 7],
         ]);
-        m3._gauss_jordan_elimination();
+        m3.gauss_jordan_elimination().unwrap();
 
-        let m3_expected = Matrix(vec![
+        let m3_expected = Matrix::from(vec![
             vec![1, 0, 0, 1, 0, -1, 2],
             vec![0, 1, 0, 0, 0, 1, 5],
             vec![0, 0, 1, 1, 0, -1, 1],
@@ -709,4 +1679,113 @@ mod test {
 
         assert_eq!(m3, m3_expected);
     }
+
+    /// Checks every null space vector solves the equations with all results 0
+    fn check_null_space(mat: &Matrix, null_space: &[Vec<Fraction>]) {
+        for vector in null_space {
+            for row in &mat.0 {
+                let sum = row
+                    .iter()
+                    .zip(vector)
+                    .try_fold(Fraction::ZERO, |acc, (&coeff, &val)| {
+                        acc.checked_add(coeff.checked_mul(val)?)
+                    })
+                    .unwrap();
+                assert!(sum.is_zero(), "{:?} . {:?}", row, vector);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reduced_matrix() {
+        // Example 1 from scratch.txt
+        // [.##.] (3) (1,3) (2) (2,3) (0,2) (0,1) {3,5,4,7}
+        let machine = Machine::from("[.##.] (3) (1,3) (2) (2,3) (0,2) (0,1) {3,5,4,7}");
+        let mat = machine.get_joltage_matrix();
+        let reduced = mat.reduce().unwrap();
+        assert_eq!(
+            reduced.mat,
+            Matrix::from(vec![
+                vec![1, 0, 0, 1, 0, -1, 2],
+                vec![0, 1, 0, 0, 0, 1, 5],
+                vec![0, 0, 1, 1, 0, -1, 1],
+                vec![0, 0, 0, 0, 1, 1, 3],
+            ])
+        );
+        assert_eq!(reduced.rank(), 4);
+        assert_eq!(reduced.pivot_columns(), &[0, 1, 2, 4]);
+        assert_eq!(reduced.free_columns(), vec![3, 5]);
+        assert!(reduced.is_consistent());
+
+        let null_space = reduced.null_space();
+        assert_eq!(
+            null_space,
+            vec![
+                [-1, 0, -1, 1, 0, 0].map(Fraction::from),
+                [1, -1, 1, 0, -1, 1].map(Fraction::from)
+            ]
+        );
+        check_null_space(&mat, &null_space);
+
+        // Free variables 0 gives a = 2, b = 5, c = 1, e = 3
+        // then d = 2, f = 1 is scratch.txt's a1 + b4 + c0 + d2 + e2 + f1 = 10
+        let mut solution = [2, 5, 1, 0, 3, 0].map(Fraction::from);
+        for (vector, times) in null_space.iter().zip([2, 1]) {
+            for (val, &step) in solution.iter_mut().zip(vector) {
+                *val = val
+                    .checked_add(step.checked_mul(Fraction::from(times)).unwrap())
+                    .unwrap();
+            }
+        }
+        assert_eq!(solution, [1, 4, 0, 2, 2, 1].map(Fraction::from));
+
+        // The one from the real input: a + d = 0, b - d = 8
+        let mat = Matrix::from(vec![
+            vec![1, 1, 1, 0, 0, 0, 0, 28],
+            vec![0, 0, 1, 0, 0, 1, 0, 36],
+            vec![1, 1, 0, 0, 1, 0, 1, 163],
+            vec![1, 0, 0, 1, 1, 1, 0, 27],
+            vec![0, 0, 1, 0, 0, 1, 1, 180],
+            vec![0, 0, 0, 0, 1, 0, 0, 11],
+            vec![1, 1, 1, 0, 0, 0, 1, 172],
+            vec![1, 1, 0, 0, 1, 0, 0, 19],
+        ]);
+        let reduced = mat.reduce().unwrap();
+        assert_eq!(reduced.rank(), 6);
+        assert_eq!(reduced.free_columns(), vec![3]);
+        assert!(reduced.is_consistent());
+        assert_eq!(
+            reduced.null_space(),
+            vec![[-1, 1, 0, 1, 0, 0, 0].map(Fraction::from)]
+        );
+        check_null_space(&mat, &reduced.null_space());
+
+        // The hard one, which needs quarters to reduce
+        let mat = Matrix::from(vec![
+            vec![0, 0, 1, 1, 0, 1, 0, 1, 0, 1, 1, 0, 57],
+            vec![0, 0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 31],
+            vec![0, 0, 0, 1, 1, 0, 0, 1, 0, 1, 1, 0, 44],
+            vec![0, 0, 1, 0, 0, 0, 0, 1, 0, 1, 1, 1, 54],
+            vec![0, 0, 1, 1, 0, 1, 1, 0, 0, 0, 1, 1, 68],
+            vec![0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 0, 0, 54],
+            vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 0, 1, 0, 52],
+            vec![0, 1, 0, 0, 0, 1, 0, 1, 0, 0, 1, 0, 48],
+            vec![0, 1, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1, 62],
+            vec![0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 1, 0, 47],
+        ]);
+        let reduced = mat.reduce().unwrap();
+        assert_eq!(reduced.rank(), 10);
+        assert_eq!(reduced.free_columns(), vec![10, 11]);
+        assert_eq!(Ok(reduced.mat.0[1][11]), Fraction::new(7, 4));
+        assert_eq!(reduced.mat.0[1][12], Fraction::from(51));
+        assert_eq!(reduced.mat.0[5][12], Fraction::from(-10));
+        check_null_space(&mat, &reduced.null_space());
+
+        // 0 = 1
+        let reduced = Matrix::from(vec![vec![1, 1, 2], vec![2, 2, 5]])
+            .reduce()
+            .unwrap();
+        assert_eq!(reduced.rank(), 1);
+        assert!(!reduced.is_consistent());
+    }
 }
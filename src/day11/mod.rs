@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub(crate) fn solve_day11() {
     // Get the input: data paths from a server
@@ -15,40 +15,233 @@ pub(crate) fn solve_day11() {
     println!("P1: There are {} paths from 'you' to 'out'", you_out_paths);
 
     // P2: Paths from svr to out that contain both fft and dac
-    let svr_dac = how_many_paths_between("svr", "dac", &servers, &mut HashMap::new());
-    let svr_fft = how_many_paths_between("svr", "fft", &servers, &mut HashMap::new());
-    let dac_fft = how_many_paths_between("dac", "fft", &servers, &mut HashMap::new());
-    let fft_dac = how_many_paths_between("fft", "dac", &servers, &mut HashMap::new());
-    let dac_out = how_many_paths_between("dac", "out", &servers, &mut HashMap::new());
-    let fft_out = how_many_paths_between("fft", "out", &servers, &mut HashMap::new());
-
-    println!(
-        "P2: Paths between:\nsvr-dac: {}\nsvr-fft: {}\ndac-fft: {}\nfft-dac: {}\ndac-out: {}\nfft-out: {}",
-        svr_dac, svr_fft, dac_fft, fft_dac, dac_out, fft_out
-    );
-
-    // Logic to find number of paths:
-    // Since there are no loops in this graph, exactly one of fft-dac and dac-fft paths will be zero
-    let (svr_first, first_second, second_out) = match (dac_fft, fft_dac) {
-        (0, _) => {
-            // FFT comes before DAC
-            (svr_fft, fft_dac, dac_out)
+    let network = Network::new(&servers).unwrap();
+    let paths = network
+        .paths_through("svr", "out", &["fft", "dac"])
+        .unwrap();
+
+    println!("P2: Paths by the order the waypoints are visited in:");
+    for (order, count) in &paths.by_order {
+        println!("svr-{}-out: {}", order.join("-"), count);
+    }
+    println!("\nTotal paths: {}", paths.total);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum PathError<'a> {
+    UnknownServer(&'a str),
+    /// The servers loop back on themselves, so there could be endless paths
+    Cycle(Vec<&'a str>),
+    /// Visited waypoints are tracked as bits of a u64
+    TooManyWaypoints(usize),
+    Overflow,
+    /// The paths counted for each order don't add up to the paths counted in total
+    OrdersDisagree {
+        total: u128,
+        by_order: u128,
+    },
+}
+
+/// The servers in topological order, so every server comes before the servers it outputs to
+#[derive(Debug)]
+struct Network<'a> {
+    names: Vec<&'a str>,
+    /// Position of each server in `names`
+    index: HashMap<&'a str, usize>,
+    /// Positions of each server's outputs
+    outputs: Vec<Vec<usize>>,
+}
+
+/// Paths from one server to another that visit every waypoint
+#[derive(Debug, PartialEq, Eq)]
+struct WaypointPaths<'a> {
+    total: u128,
+    /// Paths for each order the waypoints can be visited in, leaving out orders with no paths
+    by_order: Vec<(Vec<&'a str>, u128)>,
+}
+
+impl<'a> Network<'a> {
+    /// Sorts the servers with Kahn's algorithm
+    fn new(servers: &HashMap<&'a str, Server<'a>>) -> Result<Self, PathError<'a>> {
+        let mut waiting: HashMap<&str, usize> = servers
+            .values()
+            .map(|server| (server.name, server.inputs.len()))
+            .collect();
+        // Sorted so the order doesn't depend on the HashMap
+        let mut ready: Vec<&str> = waiting
+            .iter()
+            .filter(|&(_, &inputs)| inputs == 0)
+            .map(|(&name, _)| name)
+            .collect();
+        ready.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut names = Vec::with_capacity(servers.len());
+        while let Some(name) = ready.pop() {
+            names.push(name);
+            for &output in &servers[name].outputs {
+                let inputs = waiting.get_mut(output).unwrap();
+                *inputs -= 1;
+                if *inputs == 0 {
+                    ready.push(output);
+                }
+            }
+        }
+
+        if names.len() < servers.len() {
+            // Whatever never ran out of inputs is on or after a cycle
+            let mut stuck: Vec<&str> = waiting
+                .into_iter()
+                .filter(|&(_, inputs)| inputs > 0)
+                .map(|(name, _)| name)
+                .collect();
+            stuck.sort_unstable();
+            return Err(PathError::Cycle(stuck));
+        }
+
+        let index: HashMap<&str, usize> = names
+            .iter()
+            .enumerate()
+            .map(|(i, &name)| (name, i))
+            .collect();
+        let outputs = names
+            .iter()
+            .map(|&name| servers[name].outputs.iter().map(|o| index[o]).collect())
+            .collect();
+
+        Ok(Network {
+            names,
+            index,
+            outputs,
+        })
+    }
+
+    fn position(&self, name: &'a str) -> Result<usize, PathError<'a>> {
+        self.index
+            .get(name)
+            .copied()
+            .ok_or(PathError::UnknownServer(name))
+    }
+
+    /// Counts the paths from one server to another that visit every waypoint, in any order
+    ///
+    /// Walks the servers in topological order, counting the paths to each server
+    /// by which of the waypoints they have been through so far
+    fn count_paths(
+        &self,
+        from: &'a str,
+        to: &'a str,
+        waypoints: &[&'a str],
+    ) -> Result<u128, PathError<'a>> {
+        let from = self.position(from)?;
+        let to = self.position(to)?;
+        let mut bits: HashMap<usize, u32> = HashMap::new();
+        for &waypoint in waypoints {
+            let idx = self.position(waypoint)?;
+            let next_bit = bits.len() as u32;
+            bits.entry(idx).or_insert(next_bit);
+        }
+        if bits.len() > 64 {
+            return Err(PathError::TooManyWaypoints(bits.len()));
+        }
+        let bit = |idx: usize| bits.get(&idx).map_or(0, |&b| 1u64 << b);
+        let all = bits.values().fold(0, |mask, &b| mask | 1u64 << b);
+
+        // Only the masks a server can be reached with are kept
+        let mut counts: Vec<HashMap<u64, u128>> = vec![HashMap::new(); self.names.len()];
+        counts[from].insert(bit(from), 1);
+        for idx in from..to {
+            let current = std::mem::take(&mut counts[idx]);
+            for (mask, count) in current {
+                for &output in &self.outputs[idx] {
+                    let entry = counts[output].entry(mask | bit(output)).or_insert(0);
+                    *entry = entry.checked_add(count).ok_or(PathError::Overflow)?;
+                }
+            }
         }
-        (_, 0) => {
-            // DAC comes before FFT
-            (svr_dac, dac_fft, fft_out)
+
+        Ok(counts[to].get(&all).copied().unwrap_or(0))
+    }
+
+    /// Counts the paths from one server to another that visit every waypoint,
+    /// along with how many visit the waypoints in each order
+    fn paths_through(
+        &self,
+        from: &'a str,
+        to: &'a str,
+        waypoints: &[&'a str],
+    ) -> Result<WaypointPaths<'a>, PathError<'a>> {
+        let mut waypoints = waypoints.to_vec();
+        let mut seen = HashSet::new();
+        waypoints.retain(|&waypoint| seen.insert(waypoint));
+        let total = self.count_paths(from, to, &waypoints)?;
+
+        // Paths between every pair of stops, where the stops are
+        // from, each of the waypoints, and then to
+        let stops: Vec<&str> = std::iter::once(from)
+            .chain(waypoints.iter().copied())
+            .chain(std::iter::once(to))
+            .collect();
+        let segments = stops
+            .iter()
+            .map(|&a| stops.iter().map(|&b| self.count_paths(a, b, &[])).collect())
+            .collect::<Result<Vec<Vec<u128>>, _>>()?;
+
+        let mut by_order = Vec::new();
+        Self::orders_from(0, 1, &segments, &mut Vec::new(), &mut by_order)?;
+        let by_order: Vec<(Vec<&str>, u128)> = by_order
+            .into_iter()
+            .map(|(order, count)| (order.into_iter().map(|stop| stops[stop]).collect(), count))
+            .collect();
+
+        // With no loops every path visits the waypoints in exactly one order
+        let sum = by_order.iter().try_fold(0u128, |sum, &(_, count)| {
+            sum.checked_add(count).ok_or(PathError::Overflow)
+        })?;
+        if sum != total {
+            return Err(PathError::OrdersDisagree {
+                total,
+                by_order: sum,
+            });
         }
-        _ => {
-            panic!("AAAAHHHH");
+        Ok(WaypointPaths { total, by_order })
+    }
+
+    /// Extends the order of stops with each waypoint not yet in it, skipping any order
+    /// that already has no paths so only the reachable orders get explored
+    fn orders_from(
+        at: usize,
+        paths: u128,
+        segments: &[Vec<u128>],
+        order: &mut Vec<usize>,
+        by_order: &mut Vec<(Vec<usize>, u128)>,
+    ) -> Result<(), PathError<'a>> {
+        let last = segments.len() - 1;
+        if order.len() == last - 1 {
+            let paths = paths
+                .checked_mul(segments[at][last])
+                .ok_or(PathError::Overflow)?;
+            if paths > 0 {
+                by_order.push((order.clone(), paths));
+            }
+            return Ok(());
         }
-    };
-
-    println!(
-        "\nsvr-first: {}\nfirst-second: {}\nsecond-out: {}",
-        svr_first, first_second, second_out
-    );
-    let total = svr_first * first_second * second_out;
-    println!("\nTotal paths: {}", total);
+
+        for next in 1..last {
+            if order.contains(&next) {
+                continue;
+            }
+            let paths = paths
+                .checked_mul(segments[at][next])
+                .ok_or(PathError::Overflow)?;
+            if paths == 0 {
+                continue;
+            }
+            order.push(next);
+            Self::orders_from(next, paths, segments, order, by_order)?;
+            order.pop();
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -141,3 +334,63 @@ fn print_servers(servers: &HashMap<&str, Server>) {
         println!("{:?}", server);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_paths_through() {
+        let servers = construct_servers(include_str!("day11-test-p1.txt"));
+        let network = Network::new(&servers).unwrap();
+        let paths = how_many_paths_between("you", "out", &servers, &mut HashMap::new());
+        assert_eq!(network.count_paths("you", "out", &[]), Ok(paths as u128));
+        assert_eq!(network.count_paths("out", "you", &[]), Ok(0));
+        assert_eq!(
+            network.count_paths("you", "zzz", &[]),
+            Err(PathError::UnknownServer("zzz"))
+        );
+
+        let servers = construct_servers(include_str!("day11-test-p2.txt"));
+        let network = Network::new(&servers).unwrap();
+        let paths = network
+            .paths_through("svr", "out", &["fft", "dac"])
+            .unwrap();
+        assert_eq!(
+            paths,
+            WaypointPaths {
+                total: 2,
+                by_order: vec![(vec!["fft", "dac"], 2)],
+            }
+        );
+        // Waypoints can be given in any order, and repeated
+        let reversed = network.paths_through("svr", "out", &["dac", "fft", "dac"]);
+        assert_eq!(reversed, Ok(paths));
+        assert_eq!(
+            network
+                .paths_through("svr", "out", &["eee", "dac", "fft"])
+                .unwrap()
+                .by_order,
+            vec![(vec!["fft", "eee", "dac"], 2)]
+        );
+        // Paths to out only ever go through one of ggg and hhh
+        assert_eq!(network.count_paths("svr", "out", &["ggg", "hhh"]), Ok(0));
+
+        let servers = construct_servers(include_str!("day11-input.txt"));
+        let network = Network::new(&servers).unwrap();
+        let paths = network
+            .paths_through("svr", "out", &["fft", "dac"])
+            .unwrap();
+        assert_eq!(paths.total, 2588 * 18007824 * 10289);
+        assert_eq!(paths.by_order.len(), 1);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let servers = construct_servers("aaa: bbb\nbbb: ccc out\nccc: aaa");
+        assert_eq!(
+            Network::new(&servers).unwrap_err(),
+            PathError::Cycle(vec!["aaa", "bbb", "ccc", "out"])
+        );
+    }
+}
@@ -33,7 +33,7 @@ pub(crate) fn solve_day5() {
 
     // Count unique IDs from the ranges
     let mut unique_count: usize = 0;
-    for range in trimmed_fresh_ranges {
+    for range in &trimmed_fresh_ranges {
         let range_count = range.end() - range.start() + 1;
         unique_count += range_count;
         println!(
@@ -43,6 +43,42 @@ pub(crate) fn solve_day5() {
     }
 
     println!("There are {} unique fresh IDs", unique_count);
+
+    println!("\n\nRange analysis");
+    let merged_ranges = merge_touching_ranges(&trimmed_fresh_ranges);
+    println!(
+        "{} input ranges cover the same IDs as {} merged ranges",
+        fresh_ranges.len(),
+        merged_ranges.len()
+    );
+
+    for gap in largest_gaps(&merged_ranges, 5) {
+        println!(
+            "Gap {:?} has {} spoiled IDs",
+            gap,
+            gap.end() - gap.start() + 1
+        );
+    }
+
+    let redundancy = get_range_redundancy(&fresh_ranges);
+    for entry in &redundancy {
+        println!(
+            "Range {:?}: {} IDs also covered elsewhere, {} only covered here",
+            entry.range, entry.overlapping, entry.unique
+        );
+    }
+    let redundant: Vec<&RangeInclusive<usize>> = redundancy
+        .iter()
+        .filter(|entry| entry.is_redundant())
+        .map(|entry| &entry.range)
+        .collect();
+    println!(
+        "{} ranges can be removed on their own without changing coverage: {:?}",
+        redundant.len(),
+        redundant
+    );
+
+    println!("Minimal range list:\n{}", write_ranges(&merged_ranges));
 }
 
 fn get_ranges_and_ids(lines: Lines<'_>) -> (Vec<RangeInclusive<usize>>, Vec<usize>) {
@@ -176,6 +212,127 @@ fn get_nonoverlapping_ranges(
     trimmed_ranges
 }
 
+/// Sorts the trimmed ranges and joins any that touch end to end
+///   ***  ****     ***   =>   *******     ***
+/// Result is the smallest list of ranges covering the same IDs
+fn merge_touching_ranges(trimmed_ranges: &[RangeInclusive<usize>]) -> Vec<RangeInclusive<usize>> {
+    let mut sorted: Vec<RangeInclusive<usize>> = trimmed_ranges.to_vec();
+    sorted.sort_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<usize>> = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        if let Some(last) = merged.last_mut()
+            && *range.start() <= last.end().saturating_add(1)
+        {
+            // Overlapping or directly adjacent: extend the last range
+            if range.end() > last.end() {
+                *last = *last.start()..=*range.end();
+            }
+        } else {
+            merged.push(range);
+        }
+    }
+
+    merged
+}
+
+/// Returns the gaps of spoiled IDs between the merged ranges, largest first
+/// Only the first n gaps are kept
+fn largest_gaps(merged_ranges: &[RangeInclusive<usize>], n: usize) -> Vec<RangeInclusive<usize>> {
+    let mut gaps: Vec<RangeInclusive<usize>> = merged_ranges
+        .windows(2)
+        .map(|pair| pair[0].end() + 1..=pair[1].start() - 1)
+        .collect();
+
+    // Biggest gap first, ties go to the lowest IDs
+    gaps.sort_by(|a, b| {
+        (b.end() - b.start())
+            .cmp(&(a.end() - a.start()))
+            .then(a.start().cmp(b.start()))
+    });
+    gaps.truncate(n);
+    gaps
+}
+
+/// How much of an input range is shared with the other input ranges
+#[derive(Debug, PartialEq, Eq)]
+struct RangeRedundancy {
+    range: RangeInclusive<usize>,
+    /// Number of IDs in this range which at least one other range also covers
+    overlapping: usize,
+    /// Number of IDs which only this range covers
+    unique: usize,
+}
+
+impl RangeRedundancy {
+    /// A range is redundant if removing it by itself leaves the fresh IDs unchanged
+    /// Note: two identical ranges are each redundant, but not both at once
+    fn is_redundant(&self) -> bool {
+        self.unique == 0
+    }
+}
+
+/// Sweeps over every range boundary to count how many ranges cover each stretch of IDs
+///   ****   *****
+///     ****          Coverage: 1 2 1 0 1 1
+/// Then each input range adds up the stretches it shares with another range
+fn get_range_redundancy(full_ranges: &[RangeInclusive<usize>]) -> Vec<RangeRedundancy> {
+    // Every range starts a stretch at its start and ends one just after its end
+    let mut bounds: Vec<usize> = full_ranges
+        .iter()
+        .flat_map(|range| [*range.start(), range.end() + 1])
+        .collect();
+    bounds.sort();
+    bounds.dedup();
+
+    // Stretch i covers bounds[i]..bounds[i + 1]
+    // Count how many ranges cover each stretch using a difference array
+    let mut coverage: Vec<i64> = vec![0; bounds.len()];
+    for range in full_ranges {
+        let (first, end) = stretch_indices(&bounds, range);
+        coverage[first] += 1;
+        coverage[end] -= 1;
+    }
+    let mut count: i64 = 0;
+    for cover in coverage.iter_mut() {
+        count += *cover;
+        *cover = count;
+    }
+
+    full_ranges
+        .iter()
+        .map(|range| {
+            let (first, end) = stretch_indices(&bounds, range);
+            let overlapping: usize = (first..end)
+                .filter(|&i| coverage[i] > 1)
+                .map(|i| bounds[i + 1] - bounds[i])
+                .sum();
+            RangeRedundancy {
+                range: range.clone(),
+                overlapping,
+                unique: range.end() - range.start() + 1 - overlapping,
+            }
+        })
+        .collect()
+}
+
+/// Returns the index of the first stretch in the range and the index of the boundary just past it
+fn stretch_indices(bounds: &[usize], range: &RangeInclusive<usize>) -> (usize, usize) {
+    let first = bounds.binary_search(range.start()).unwrap();
+    let end = bounds.binary_search(&(range.end() + 1)).unwrap();
+    (first, end)
+}
+
+/// Writes the ranges back out the same way as the input file
+/// 3-5
+/// 10-20
+fn write_ranges(ranges: &[RangeInclusive<usize>]) -> String {
+    ranges
+        .iter()
+        .map(|range| format!("{}-{}\n", range.start(), range.end()))
+        .collect()
+}
+
 /// This did not complete after 20 minutes ... worth a try lol
 fn _get_unique_ids_brute_force(full_ranges: &Vec<RangeInclusive<usize>>) -> Vec<usize> {
     let mut unique: Vec<usize> = Vec::with_capacity(full_ranges.len());
@@ -190,3 +347,47 @@ fn _get_unique_ids_brute_force(full_ranges: &Vec<RangeInclusive<usize>>) -> Vec<
 
     unique
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_range_analysis() {
+        let (fresh_ranges, _) = get_ranges_and_ids(include_str!("day5-test.txt").lines());
+        let merged = merge_touching_ranges(&get_nonoverlapping_ranges(&fresh_ranges));
+        assert_eq!(merged, vec![3..=5, 10..=20, 40..=50]);
+        assert_eq!(largest_gaps(&merged, 5), vec![21..=39, 6..=9]);
+        assert_eq!(largest_gaps(&merged, 1), vec![21..=39]);
+
+        // Re-parsing the written list gives back the same ranges
+        let written = write_ranges(&merged);
+        assert_eq!(written, "3-5\n10-20\n40-50\n");
+        assert_eq!(get_ranges_and_ids(written.lines()).0, merged);
+
+        // 3-5 and 6-8 touch, so they join up
+        assert_eq!(
+            merge_touching_ranges(&[6..=8, 3..=5, 12..=12]),
+            vec![3..=8, 12..=12]
+        );
+    }
+
+    #[test]
+    fn test_range_redundancy() {
+        let redundancy = get_range_redundancy(&[10..=14, 16..=20, 12..=18, 40..=50, 42..=45]);
+        let expected = [
+            (10..=14, 3, 2),
+            (16..=20, 3, 2),
+            (12..=18, 6, 1),
+            (40..=50, 4, 7),
+            (42..=45, 4, 0),
+        ];
+        for (entry, (range, overlapping, unique)) in redundancy.iter().zip(expected) {
+            assert_eq!(entry.range, range);
+            assert_eq!(entry.overlapping, overlapping);
+            assert_eq!(entry.unique, unique);
+        }
+        assert!(redundancy[4].is_redundant());
+        assert!(!redundancy[2].is_redundant());
+    }
+}
@@ -1,24 +1,88 @@
-use std::str::Lines;
+use std::fmt::Display;
+use std::ops::Range;
 
 pub(crate) fn solve_day6() {
     // Get the math homework
     let binding = include_str!("day6-input.txt");
     // let binding = include_str!("day6-test.txt");
-    let lines: Lines<'_> = binding.lines();
 
-    let homework = parse_input(lines);
+    let worksheet = match Worksheet::parse(binding) {
+        Ok(worksheet) => worksheet,
+        Err(err) => {
+            println!("Could not read the homework: {:?}", err);
+            return;
+        }
+    };
+
+    // See if the whole sheet sticks to one alignment
+    for orientation in [Orientation::LeftAligned, Orientation::RightAligned] {
+        match worksheet.numbers(orientation) {
+            Ok(_) => println!("Every number is {:?}", orientation),
+            Err(err) => println!("Not every number is {:?}: {:?}", orientation, err),
+        }
+    }
+
+    let homework = match Homework::try_from(worksheet) {
+        Ok(homework) => homework,
+        Err(err) => {
+            println!("Could not read the homework: {:?}", err);
+            return;
+        }
+    };
 
-    let sum_p1 = Homework::solve(&homework.nums_p1, &homework.ops);
-    println!("The total part 1 sum is: {}", sum_p1);
+    let results_p1 = Homework::solve(&homework.nums_p1, &homework.ops);
+    print_failed_columns(&results_p1);
+    match Homework::total(&results_p1) {
+        Ok(sum_p1) => println!("The total part 1 sum is: {}", sum_p1),
+        Err(err) => println!("The total part 1 sum could not be found: {:?}", err),
+    }
+
+    let results_p2 = Homework::solve(&homework.nums_p2, &homework.ops);
+    print_failed_columns(&results_p2);
+    match Homework::total(&results_p2) {
+        Ok(sum_p2) => println!("The total part 2 sum is: {}", sum_p2),
+        Err(err) => println!("The total part 2 sum could not be found: {:?}", err),
+    }
 
-    let sum_p2 = Homework::solve(&homework.nums_p2, &homework.ops);
-    println!("The total part 2 sum is: {}", sum_p2);
+    // Write the homework back out and make sure nothing got lost on the way in
+    match homework.write(Orientation::Rows) {
+        Ok(written) => println!(
+            "Writing the homework back out matches the input: {}",
+            written == binding
+        ),
+        Err(err) => println!("Could not write the homework back out: {:?}", err),
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Operation {
     Add,
     Multiply,
+    Subtract,
+    Divide,
+    Min,
+    Max,
+    Power,
+}
+
+/// Which end of the column an operation starts folding from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fold {
+    /// ((a - b) - c)
+    LeftToRight,
+    /// a ^ (b ^ c)
+    RightToLeft,
+}
+
+/// Why a column could not be worked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnError {
+    /// The column has no numbers in it
+    Empty,
+    /// lhs op rhs does not fit in a u64 (including subtracting below 0)
+    Overflow { op: Operation, lhs: u64, rhs: u64 },
+    /// lhs / 0
+    DivideByZero { lhs: u64 },
 }
 
 impl TryFrom<String> for Operation {
@@ -28,143 +92,950 @@ impl TryFrom<String> for Operation {
         match value.trim() {
             "*" => Ok(Self::Multiply),
             "+" => Ok(Self::Add),
+            "-" => Ok(Self::Subtract),
+            "/" => Ok(Self::Divide),
+            "<" => Ok(Self::Min),
+            ">" => Ok(Self::Max),
+            "^" => Ok(Self::Power),
             _ => Err("Invalid".to_string()),
         }
     }
 }
 
-#[derive(Debug)]
+impl Operation {
+    /// The character written under a block for this operation
+    fn symbol(&self) -> char {
+        match self {
+            Operation::Add => '+',
+            Operation::Multiply => '*',
+            Operation::Subtract => '-',
+            Operation::Divide => '/',
+            Operation::Min => '<',
+            Operation::Max => '>',
+            Operation::Power => '^',
+        }
+    }
+
+    /// Exponents stack up from the bottom of the column, everything else goes top down
+    ///  2
+    ///  3   => 2 ^ (3 ^ 2) = 512
+    ///  2
+    ///  ^
+    fn fold_direction(&self) -> Fold {
+        match self {
+            Operation::Power => Fold::RightToLeft,
+            _ => Fold::LeftToRight,
+        }
+    }
+
+    /// Performs a single step of the operation without wrapping or panicking
+    fn apply(&self, lhs: u64, rhs: u64) -> Result<u64, ColumnError> {
+        let overflow = ColumnError::Overflow {
+            op: *self,
+            lhs,
+            rhs,
+        };
+        match self {
+            Operation::Add => lhs.checked_add(rhs).ok_or(overflow),
+            Operation::Multiply => lhs.checked_mul(rhs).ok_or(overflow),
+            Operation::Subtract => lhs.checked_sub(rhs).ok_or(overflow),
+            Operation::Divide => lhs
+                .checked_div(rhs)
+                .ok_or(ColumnError::DivideByZero { lhs }),
+            Operation::Min => Ok(lhs.min(rhs)),
+            Operation::Max => Ok(lhs.max(rhs)),
+            // 0 and 1 stay put for any exponent, even one too big for checked_pow
+            Operation::Power if lhs <= 1 && rhs > 0 => Ok(lhs),
+            Operation::Power => u32::try_from(rhs)
+                .ok()
+                .and_then(|exp| lhs.checked_pow(exp))
+                .ok_or(overflow),
+        }
+    }
+
+    /// Folds the operation over all the numbers of a column in its fold direction
+    fn fold(&self, nums: &[u64]) -> Result<u64, ColumnError> {
+        match self.fold_direction() {
+            Fold::LeftToRight => {
+                let (&first, rest) = nums.split_first().ok_or(ColumnError::Empty)?;
+                rest.iter()
+                    .try_fold(first, |accum, &num| self.apply(accum, num))
+            }
+            Fold::RightToLeft => {
+                let (&last, rest) = nums.split_last().ok_or(ColumnError::Empty)?;
+                rest.iter()
+                    .rev()
+                    .try_fold(last, |accum, &num| self.apply(num, accum))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
 struct Homework {
     nums_p1: Vec<Vec<Option<u64>>>, // Numbers parsed the usual way
     nums_p2: Vec<Vec<Option<u64>>>, // Numbers parsed in R-L column way
     ops: Vec<Operation>,
 }
 
-impl Homework {
-    fn solve(nums: &Vec<Vec<Option<u64>>>, ops: &Vec<Operation>) -> u64 {
-        // println!("Solve: {:?}\n{:?}", nums, ops);
+/// The worked out answer for one column of the homework
+#[derive(Debug, PartialEq, Eq)]
+struct ColumnResult {
+    op: Operation,
+    nums: Vec<u64>,
+    result: Result<u64, ColumnError>,
+}
 
-        // Parse through the grid and do the operation on the columns
-        let cols = ops.len();
-        let rows = nums.len();
-        let mut accum: u64 = 0;
+impl Homework {
+    /// Works out every column of the grid with its operation
+    fn solve(nums: &[Vec<Option<u64>>], ops: &[Operation]) -> Vec<ColumnResult> {
         println!(
             "Solve: cols = {}, rows = {}, #ops = {}",
-            cols,
-            rows,
+            ops.len(),
+            nums.len(),
             ops.len()
         );
-        for r in 0..nums.len() {
-            println!("  nums[{}] len = {}", r, nums[r].len());
+
+        // Parse through the grid and do the operation on the columns
+        // Missing numbers in a column are skipped
+        ops.iter()
+            .enumerate()
+            .map(|(c, &op)| {
+                let col_nums: Vec<u64> = nums.iter().filter_map(|row| row[c]).collect();
+                ColumnResult {
+                    op,
+                    result: op.fold(&col_nums),
+                    nums: col_nums,
+                }
+            })
+            .collect()
+    }
+
+    /// Adds up the answers to all the columns
+    /// Fails with the first column that could not be worked out
+    fn total(results: &[ColumnResult]) -> Result<u64, ColumnError> {
+        results.iter().try_fold(0u64, |accum, col| {
+            let col_res = col.result?;
+            Operation::Add.apply(accum, col_res)
+        })
+    }
+}
+
+fn print_failed_columns(results: &[ColumnResult]) {
+    for (c, col) in results.iter().enumerate() {
+        if let Err(err) = col.result {
+            println!(
+                "  Column {} {:?} {:?} failed: {:?}",
+                c, col.op, col.nums, err
+            );
         }
+    }
+}
 
-        for c in 0..cols {
-            let mut col_res: u64 = nums[0][c].unwrap();
-            for r in 1..rows {
-                // println!("r={}/{}, c={}/{}, cur={} new={}", r, nums[r].len(), c, cols, col_res, nums[r][c]);
-                if let Some(num) = nums[r][c] {
-                    match ops[c] {
-                        Operation::Add => col_res += num,
-                        Operation::Multiply => col_res *= num,
-                    }
+/// How many columns a tab moves across to
+const TAB_WIDTH: usize = 8;
+
+/// How the digits of each number are laid out inside a column block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Orientation {
+    /// Read across each row, the number can sit anywhere in the block
+    Rows,
+    /// Read across each row, the number must start at the left edge of the block
+    /// 123
+    /// 45
+    LeftAligned,
+    /// Read across each row, the number must end at the right edge of the block
+    /// 123
+    ///  45
+    RightAligned,
+    /// Read down each column of characters, right to left (part 2)
+    /// 123
+    ///  45   => 356, 24, 1
+    ///   6
+    Vertical,
+}
+
+/// Where the worksheet failed to parse
+/// Rows are lines of the input and columns are after tabs are expanded, both count from 0
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParseError {
+    row: usize,
+    col: usize,
+    kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ParseErrorKind {
+    /// There are no lines in the worksheet
+    NoOperations,
+    /// Something other than a digit or blank where a number should be
+    InvalidCharacter(char),
+    /// The number does not sit against the edge its orientation requires
+    Misaligned,
+    /// The number does not fit in a u64
+    TooLarge,
+    /// A column of characters in a vertical block has no digits in it
+    EmptyDigitColumn,
+    /// The block has no operation under it
+    MissingOperation,
+    /// The block has something under it that isn't a single operation
+    InvalidOperation(String),
+}
+
+impl ParseError {
+    fn new(row: usize, col: usize, kind: ParseErrorKind) -> Self {
+        Self { row, col, kind }
+    }
+}
+
+/// The worksheet split up into its blocks of columns
+/// 123 328  51 64
+///  45 64  387 23
+///   6 98  215 314
+/// *   +   *   +
+/// |-| |-| |-| |-|  <- blocks are separated by columns which are blank in every row
+#[derive(Debug)]
+struct Worksheet {
+    /// Number rows, with tabs expanded and all padded to the same length
+    grid: Vec<Vec<char>>,
+    /// Input line each number row came from
+    lines: Vec<usize>,
+    /// Input line the operations came from
+    op_line: usize,
+    /// Character columns covered by each block
+    blocks: Vec<Range<usize>>,
+    ops: Vec<Operation>,
+}
+
+impl Worksheet {
+    /// Splits the worksheet into blocks and reads the operation under each of them
+    /// The last line with anything on it holds the operations
+    fn parse(input: &str) -> Result<Self, ParseError> {
+        // Blank lines don't hold any numbers, but still count towards the line numbers
+        let (mut lines, mut grid): (Vec<usize>, Vec<Vec<char>>) = input
+            .lines()
+            .map(expand_tabs)
+            .enumerate()
+            .filter(|(_, row)| row.iter().any(|c| !c.is_whitespace()))
+            .unzip();
+        let op_row = grid
+            .pop()
+            .ok_or(ParseError::new(0, 0, ParseErrorKind::NoOperations))?;
+        let op_line = lines.pop().unwrap();
+
+        // Pad every row out so ragged lines can be indexed like the longest one
+        let width = grid
+            .iter()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            .max(op_row.len());
+        grid.iter_mut().for_each(|row| row.resize(width, ' '));
+        let mut op_row = op_row;
+        op_row.resize(width, ' ');
+
+        // A column is part of a block if anything in any row is written in it
+        let mut blocks: Vec<Range<usize>> = Vec::new();
+        let mut block_start: Option<usize> = None;
+        for c in 0..=width {
+            let used = c < width
+                && (!op_row[c].is_whitespace() || grid.iter().any(|row| !row[c].is_whitespace()));
+            match (used, block_start) {
+                (true, None) => block_start = Some(c),
+                (false, Some(start)) => {
+                    blocks.push(start..c);
+                    block_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        // Each block has exactly one operation under it
+        let mut ops: Vec<Operation> = Vec::with_capacity(blocks.len());
+        for block in &blocks {
+            let symbols: String = op_row[block.clone()].iter().collect();
+            let symbols = symbols.trim();
+            if symbols.is_empty() {
+                return Err(ParseError::new(
+                    op_line,
+                    block.start,
+                    ParseErrorKind::MissingOperation,
+                ));
+            }
+            let op = Operation::try_from(symbols.to_string()).map_err(|_| {
+                let col = block.start
+                    + op_row[block.clone()]
+                        .iter()
+                        .take_while(|c| c.is_whitespace())
+                        .count();
+                ParseError::new(
+                    op_line,
+                    col,
+                    ParseErrorKind::InvalidOperation(symbols.to_string()),
+                )
+            })?;
+            ops.push(op);
+        }
+
+        Ok(Self {
+            grid,
+            lines,
+            op_line,
+            blocks,
+            ops,
+        })
+    }
+
+    /// Reads the numbers out of every block in the given orientation
+    /// Result is indexed [number][block], blocks with fewer numbers are filled out with None
+    fn numbers(&self, orientation: Orientation) -> Result<Vec<Vec<Option<u64>>>, ParseError> {
+        let mut cols: Vec<Vec<Option<u64>>> = Vec::with_capacity(self.blocks.len());
+        for block in &self.blocks {
+            let col = match orientation {
+                Orientation::Vertical => self.vertical_numbers(block)?,
+                _ => self
+                    .grid
+                    .iter()
+                    .enumerate()
+                    .map(|(r, row)| {
+                        parse_cell(&row[block.clone()], self.lines[r], block.start, orientation)
+                    })
+                    .collect::<Result<Vec<Option<u64>>, ParseError>>()?,
+            };
+            cols.push(col);
+        }
+
+        // Flip [block][number] around to [number][block]
+        let count = cols.iter().map(Vec::len).max().unwrap_or(0);
+        Ok((0..count)
+            .map(|n| {
+                cols.iter()
+                    .map(|col| col.get(n).copied().flatten())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Reads each column of characters in the block top to bottom, starting from the right
+    fn vertical_numbers(&self, block: &Range<usize>) -> Result<Vec<Option<u64>>, ParseError> {
+        let mut nums: Vec<Option<u64>> = Vec::with_capacity(block.len());
+        for c in block.clone().rev() {
+            let mut num: Option<u64> = None;
+            for (r, row) in self.grid.iter().enumerate() {
+                let char = row[c];
+                if char.is_whitespace() {
+                    continue;
                 }
+                num = Some(push_digit(num.unwrap_or(0), char, self.lines[r], c)?);
             }
+            match num {
+                Some(num) => nums.push(Some(num)),
+                // Only the operation was written in this column
+                None => {
+                    return Err(ParseError::new(
+                        self.op_line,
+                        c,
+                        ParseErrorKind::EmptyDigitColumn,
+                    ));
+                }
+            }
+        }
+        Ok(nums)
+    }
+}
+
+/// Reads the number in one row of a block
+/// Blank cells are None
+fn parse_cell(
+    cell: &[char],
+    row: usize,
+    start_col: usize,
+    orientation: Orientation,
+) -> Result<Option<u64>, ParseError> {
+    let Some(first) = cell.iter().position(|c| !c.is_whitespace()) else {
+        return Ok(None);
+    };
+    let last = cell.iter().rposition(|c| !c.is_whitespace()).unwrap();
 
-            accum += col_res;
+    match orientation {
+        Orientation::LeftAligned if first != 0 => {
+            return Err(ParseError::new(
+                row,
+                start_col + first,
+                ParseErrorKind::Misaligned,
+            ));
         }
+        Orientation::RightAligned if last != cell.len() - 1 => {
+            return Err(ParseError::new(
+                row,
+                start_col + last,
+                ParseErrorKind::Misaligned,
+            ));
+        }
+        _ => {}
+    }
 
-        accum
+    let mut num: u64 = 0;
+    for (idx, &char) in cell.iter().enumerate().take(last + 1).skip(first) {
+        num = push_digit(num, char, row, start_col + idx)?;
     }
+    Ok(Some(num))
 }
 
-fn parse_input(lines: Lines) -> Homework {
-    let mut nums_p1: Vec<Vec<Option<u64>>> = Vec::new();
-    let mut ops: Vec<Operation> = Vec::new();
-    let chars_p2: Vec<Vec<char>> = lines.clone().map(|s| s.chars().collect()).collect();
+/// Shifts another digit onto the end of the number
+fn push_digit(num: u64, char: char, row: usize, col: usize) -> Result<u64, ParseError> {
+    let digit = char.to_digit(10).ok_or(ParseError::new(
+        row,
+        col,
+        ParseErrorKind::InvalidCharacter(char),
+    ))?;
+    num.checked_mul(10)
+        .and_then(|num| num.checked_add(digit as u64))
+        .ok_or(ParseError::new(row, col, ParseErrorKind::TooLarge))
+}
 
-    // P1 nums and operations
-    for line in lines {
-        let first = char::from(line.as_bytes()[0]);
-        let split = line.split_ascii_whitespace();
-        // See if this line is a number or your operations
-        if first == '*' || first == '+' {
-            // Operations
-            ops = split
-                .map(|s| Operation::try_from(s.to_string()).unwrap())
-                .collect();
-            break; // That's all folks
+/// Turns tabs into spaces up to the next tab stop
+fn expand_tabs(line: &str) -> Vec<char> {
+    let mut chars: Vec<char> = Vec::with_capacity(line.len());
+    for char in line.chars() {
+        if char == '\t' {
+            let stop = (chars.len() / TAB_WIDTH + 1) * TAB_WIDTH;
+            chars.resize(stop, ' ');
         } else {
-            // Number
-            nums_p1.push(
-                split
-                    .map(|s| Some(u64::from_str_radix(s, 10).unwrap()))
-                    .collect(),
-            );
+            chars.push(char);
+        }
+    }
+    chars
+}
+
+impl TryFrom<Worksheet> for Homework {
+    type Error = ParseError;
+
+    fn try_from(worksheet: Worksheet) -> Result<Self, Self::Error> {
+        Ok(Self {
+            nums_p1: worksheet.numbers(Orientation::Rows)?,
+            nums_p2: worksheet.numbers(Orientation::Vertical)?,
+            ops: worksheet.ops,
+        })
+    }
+}
+
+/// Why the homework could not be written out as a worksheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteError {
+    /// The block has no numbers, so there is nothing to hold its operation
+    EmptyBlock(usize),
+    /// The row has no numbers and would be skipped when reading the worksheet back
+    EmptyRow(usize),
+    /// The block can't be laid out so both ways of reading it give back its numbers
+    Mismatch(usize),
+}
+
+impl Homework {
+    /// Writes the homework out as a worksheet that reads back in to the same numbers both ways
+    /// LeftAligned and RightAligned line up the rows of every block that way
+    /// Rows picks whichever of the two each block needs to match its part 2 numbers
+    /// Vertical stacks the part 2 digits from the top or the bottom of each block,
+    /// whichever matches its part 1 numbers
+    fn write(&self, orientation: Orientation) -> Result<String, WriteError> {
+        let height = self.nums_p1.len();
+        let mut block_grids: Vec<Vec<Vec<char>>> = Vec::with_capacity(self.ops.len());
+        for c in 0..self.ops.len() {
+            let col_p1: Vec<Option<u64>> = self.nums_p1.iter().map(|row| row[c]).collect();
+            let col_p2: Vec<Option<u64>> = self.nums_p2.iter().map(|row| row[c]).collect();
+
+            let layouts = match orientation {
+                Orientation::Rows => vec![
+                    layout_rows(&col_p1, Orientation::LeftAligned),
+                    layout_rows(&col_p1, Orientation::RightAligned),
+                ],
+                Orientation::Vertical => vec![
+                    layout_vertical(&col_p2, height, true),
+                    layout_vertical(&col_p2, height, false),
+                ],
+                _ => vec![layout_rows(&col_p1, orientation)],
+            };
+            let layouts: Vec<Vec<Vec<char>>> = layouts
+                .into_iter()
+                .collect::<Option<_>>()
+                .ok_or(WriteError::EmptyBlock(c))?;
+
+            // Keep the first layout that reads back the same both ways
+            let block_grid = layouts
+                .into_iter()
+                .find(|grid| reads_back_as(grid, &col_p1, &col_p2))
+                .ok_or(WriteError::Mismatch(c))?;
+            block_grids.push(block_grid);
+        }
+
+        let worksheet = Worksheet::join(block_grids, &self.ops);
+        if let Some(r) =
+            (0..worksheet.grid.len()).find(|&r| worksheet.grid[r].iter().all(|c| c.is_whitespace()))
+        {
+            return Err(WriteError::EmptyRow(r));
         }
+
+        Ok(worksheet.to_string())
     }
+}
+
+impl Worksheet {
+    /// Puts the blocks side by side with a blank column between each of them
+    /// Shorter blocks are padded out with blank rows at the bottom
+    fn join(block_grids: Vec<Vec<Vec<char>>>, ops: &[Operation]) -> Self {
+        let height = block_grids.iter().map(Vec::len).max().unwrap_or(0);
+        let mut grid: Vec<Vec<char>> = vec![Vec::new(); height];
+        let mut blocks: Vec<Range<usize>> = Vec::with_capacity(block_grids.len());
 
-    // P2 nums
-    let op_row: &Vec<char> = &chars_p2[chars_p2.len() - 1];
-    let count_rows = nums_p1.len();
-    let mut op_idx: usize = 0;
-    let mut end_idx: usize;
-    // Allocate P2 vec
-    let mut nums_p2: Vec<Vec<Option<u64>>> = Vec::with_capacity(count_rows);
-    for r in 0..count_rows {
-        nums_p2.push(Vec::with_capacity(nums_p1[r].len()));
-    }
-
-    // let mut row: usize = 0;
-    while op_idx < op_row.len() {
-        end_idx = get_end_idx(op_row, op_idx);
-        // println!("Op idx: {}, End idx: {}", op_idx, end_idx);
-        // We have the bounds for our numbers. Parse the columns
-        // 123
-        //  4   => 35, 24, 1
-        //   5
-        // There's always a full column space between the numbers and next operation
-        let nums = parse_col_num(op_idx, end_idx, count_rows, &chars_p2);
-        for r in 0..count_rows {
-            nums_p2[r].push(nums.get(r).copied());
-        }
-        op_idx = end_idx;
-        // row += 1;
-    }
-
-    Homework {
-        nums_p1,
-        nums_p2,
-        ops,
-    }
-}
-
-fn parse_col_num(
-    op_idx: usize,
-    end_idx: usize,
-    count_rows: usize,
-    chars_p2: &Vec<Vec<char>>,
-) -> Vec<u64> {
-    let mut nums: Vec<u64> = Vec::new();
-    for c in (op_idx..end_idx - 1).rev() {
-        let mut num_str = String::with_capacity(count_rows);
-        for r in 0..count_rows {
-            let char = chars_p2[r][c];
-            if char >= '0' && char <= '9' {
-                num_str.push(char);
+        for block_grid in block_grids {
+            let start = grid[0].len() + if blocks.is_empty() { 0 } else { 1 };
+            let width = block_grid[0].len();
+            for (r, row) in grid.iter_mut().enumerate() {
+                row.resize(start, ' ');
+                match block_grid.get(r) {
+                    Some(block_row) => row.extend(block_row),
+                    None => row.resize(start + width, ' '),
+                }
             }
+            blocks.push(start..start + width);
+        }
+
+        Self {
+            lines: (0..grid.len()).collect(),
+            op_line: grid.len(),
+            grid,
+            blocks,
+            ops: ops.to_vec(),
         }
-        // println!("Col {}: {}", c - op_idx, num_str);
-        nums.push(u64::from_str_radix(&num_str, 10).unwrap());
     }
-    nums
 }
 
-fn get_end_idx(op_row: &Vec<char>, cur: usize) -> usize {
-    for (idx, &char) in op_row[cur + 1..].iter().enumerate() {
-        if char == '+' || char == '*' {
-            return cur + 1 + idx;
+impl Display for Worksheet {
+    /// Writes the number rows and then the operations under the left edge of each block
+    /// Every line is padded out to the full width, like the puzzle input
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let width = self.blocks.last().map_or(0, |block| block.end);
+        for row in &self.grid {
+            writeln!(f, "{}", row.iter().collect::<String>())?;
         }
+
+        let mut op_row: Vec<char> = vec![' '; width];
+        for (block, op) in self.blocks.iter().zip(&self.ops) {
+            op_row[block.start] = op.symbol();
+        }
+        writeln!(f, "{}", op_row.iter().collect::<String>())
+    }
+}
+
+/// Writes one block's numbers on their rows, padded out to the widest number
+/// Returns None if the block has no numbers
+fn layout_rows(nums: &[Option<u64>], align: Orientation) -> Option<Vec<Vec<char>>> {
+    let width = nums
+        .iter()
+        .flatten()
+        .map(|num| num.to_string().len())
+        .max()?;
+    Some(
+        nums.iter()
+            .map(|num| {
+                let num = num.map(|num| num.to_string()).unwrap_or_default();
+                let cell = match align {
+                    Orientation::LeftAligned => format!("{:<width$}", num),
+                    _ => format!("{:>width$}", num),
+                };
+                cell.chars().collect()
+            })
+            .collect(),
+    )
+}
+
+/// Writes one block's part 2 numbers down its columns, the first number on the right
+/// The digits either hang down from the top row or sit up from the bottom row
+///  356, 24, 1  =>  123  or    6
+///                   45       45
+///                    6      123
+/// Returns None if the block has no numbers
+fn layout_vertical(nums: &[Option<u64>], height: usize, from_top: bool) -> Option<Vec<Vec<char>>> {
+    let digits: Vec<Vec<char>> = nums
+        .iter()
+        .map_while(|num| num.map(|num| num.to_string().chars().collect()))
+        .collect();
+    let width = digits.len();
+    if width == 0 {
+        return None;
     }
 
-    op_row.len() + 1
+    let height = digits.iter().map(Vec::len).max().unwrap_or(0).max(height);
+    let mut grid: Vec<Vec<char>> = vec![vec![' '; width]; height];
+    for (n, num) in digits.iter().enumerate() {
+        let top = if from_top { 0 } else { height - num.len() };
+        for (r, &digit) in num.iter().enumerate() {
+            grid[top + r][width - 1 - n] = digit;
+        }
+    }
+    Some(grid)
+}
+
+/// Checks that a single block reads back in as the given numbers both ways
+fn reads_back_as(grid: &[Vec<char>], col_p1: &[Option<u64>], col_p2: &[Option<u64>]) -> bool {
+    let block = 0..grid[0].len();
+    let worksheet = Worksheet {
+        grid: grid.to_vec(),
+        lines: (0..grid.len()).collect(),
+        op_line: grid.len(),
+        blocks: vec![block],
+        ops: vec![Operation::Add],
+    };
+
+    // Compare ignoring any blank entries on the end
+    let same = |read: Result<Vec<Vec<Option<u64>>>, ParseError>, expected: &[Option<u64>]| {
+        read.is_ok_and(|read| {
+            let len = read.len().max(expected.len());
+            (0..len)
+                .all(|n| read.get(n).and_then(|row| row[0]) == expected.get(n).copied().flatten())
+        })
+    };
+
+    same(worksheet.numbers(Orientation::Rows), col_p1)
+        && same(worksheet.numbers(Orientation::Vertical), col_p2)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rng::XorShift;
+
+    #[test]
+    fn test_test_input() {
+        let worksheet = Worksheet::parse(include_str!("day6-test.txt")).unwrap();
+        let homework = Homework::try_from(worksheet).unwrap();
+        let results_p1 = Homework::solve(&homework.nums_p1, &homework.ops);
+        assert_eq!(results_p1[0].result, Ok(33210));
+        assert_eq!(Homework::total(&results_p1), Ok(4277556));
+
+        let results_p2 = Homework::solve(&homework.nums_p2, &homework.ops);
+        assert_eq!(Homework::total(&results_p2), Ok(3263827));
+    }
+
+    #[test]
+    fn test_fold_operations() {
+        assert_eq!(Operation::Subtract.fold(&[20, 5, 3]), Ok(12));
+        assert_eq!(Operation::Divide.fold(&[100, 5, 2]), Ok(10));
+        assert_eq!(Operation::Min.fold(&[7, 3, 9]), Ok(3));
+        assert_eq!(Operation::Max.fold(&[7, 3, 9]), Ok(9));
+        assert_eq!(Operation::Power.fold(&[2, 3, 2]), Ok(512));
+        assert_eq!(Operation::Add.fold(&[]), Err(ColumnError::Empty));
+        assert_eq!(
+            Operation::Subtract.fold(&[3, 5]),
+            Err(ColumnError::Overflow {
+                op: Operation::Subtract,
+                lhs: 3,
+                rhs: 5
+            })
+        );
+        assert_eq!(
+            Operation::Divide.fold(&[3, 0]),
+            Err(ColumnError::DivideByZero { lhs: 3 })
+        );
+        assert!(Operation::Power.fold(&[10, 30]).is_err());
+        assert!(Operation::Power.fold(&[2, u64::MAX]).is_err());
+        assert_eq!(Operation::Power.fold(&[1, u64::MAX]), Ok(1));
+        assert_eq!(Operation::Power.fold(&[0, u64::MAX]), Ok(0));
+        assert_eq!(Operation::Power.fold(&[0, 0]), Ok(1));
+        assert!(Operation::Multiply.fold(&[u64::MAX, 2]).is_err());
+    }
+
+    #[test]
+    fn test_column_breakdown() {
+        let nums = vec![
+            vec![Some(20), Some(2), Some(4)],
+            vec![Some(5), Some(3), None],
+            vec![Some(30), Some(2), Some(6)],
+        ];
+        let ops = vec![Operation::Subtract, Operation::Power, Operation::Max];
+        let results = Homework::solve(&nums, &ops);
+        assert_eq!(results[1].nums, vec![2, 3, 2]);
+        assert_eq!(results[1].result, Ok(512));
+        assert_eq!(results[2].result, Ok(6));
+        assert!(results[0].result.is_err());
+        assert_eq!(Homework::total(&results), results[0].result);
+    }
+
+    #[test]
+    fn test_worksheet_layouts() {
+        let worksheet = Worksheet::parse("123 4\n 45 56\n  6 7\n*   +").unwrap();
+        assert_eq!(worksheet.blocks, vec![0..3, 4..6]);
+        assert_eq!(worksheet.ops, vec![Operation::Multiply, Operation::Add]);
+        assert_eq!(
+            worksheet.numbers(Orientation::Rows).unwrap(),
+            vec![
+                vec![Some(123), Some(4)],
+                vec![Some(45), Some(56)],
+                vec![Some(6), Some(7)]
+            ]
+        );
+        assert_eq!(
+            worksheet.numbers(Orientation::Vertical).unwrap(),
+            vec![
+                vec![Some(356), Some(6)],
+                vec![Some(24), Some(457)],
+                vec![Some(1), None]
+            ]
+        );
+        assert_eq!(
+            worksheet.numbers(Orientation::RightAligned),
+            Err(ParseError::new(0, 4, ParseErrorKind::Misaligned))
+        );
+        assert_eq!(
+            worksheet.numbers(Orientation::LeftAligned),
+            Err(ParseError::new(1, 1, ParseErrorKind::Misaligned))
+        );
+    }
+
+    #[test]
+    fn test_ragged_and_tabs() {
+        // Short lines and a tab lining the second block up at column 8
+        let worksheet = Worksheet::parse("12\t3\n1\n\n-       /\n\n").unwrap();
+        assert_eq!(worksheet.blocks, vec![0..2, 8..9]);
+        assert_eq!(
+            worksheet.numbers(Orientation::LeftAligned).unwrap(),
+            vec![vec![Some(12), Some(3)], vec![Some(1), None]]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Worksheet::parse("12 3\n4x 5\n+  *")
+                .unwrap()
+                .numbers(Orientation::Rows),
+            Err(ParseError::new(1, 1, ParseErrorKind::InvalidCharacter('x')))
+        );
+        assert_eq!(
+            Worksheet::parse("12 3\n4x 5\n+  *")
+                .unwrap()
+                .numbers(Orientation::Vertical),
+            Err(ParseError::new(1, 1, ParseErrorKind::InvalidCharacter('x')))
+        );
+        assert_eq!(
+            Worksheet::parse("12 3\n45 6\n+").unwrap_err(),
+            ParseError::new(2, 3, ParseErrorKind::MissingOperation)
+        );
+        assert_eq!(
+            Worksheet::parse("12 3\n45 6\n+  %").unwrap_err(),
+            ParseError::new(2, 3, ParseErrorKind::InvalidOperation("%".to_string()))
+        );
+        assert_eq!(
+            Worksheet::parse("99999999999999999999\n+")
+                .unwrap()
+                .numbers(Orientation::Rows),
+            Err(ParseError::new(0, 19, ParseErrorKind::TooLarge))
+        );
+        assert_eq!(
+            Worksheet::parse("1 \n2 \n +")
+                .unwrap()
+                .numbers(Orientation::Vertical),
+            Err(ParseError::new(2, 1, ParseErrorKind::EmptyDigitColumn))
+        );
+        // Rows are counted by input line, even past blank ones
+        assert_eq!(
+            Worksheet::parse("12 3\n\n4x 5\n\n+  *")
+                .unwrap()
+                .numbers(Orientation::Rows),
+            Err(ParseError::new(2, 1, ParseErrorKind::InvalidCharacter('x')))
+        );
+        assert_eq!(
+            Worksheet::parse("\n1 \n2 \n\n +")
+                .unwrap()
+                .numbers(Orientation::Vertical),
+            Err(ParseError::new(4, 1, ParseErrorKind::EmptyDigitColumn))
+        );
+        assert_eq!(
+            Worksheet::parse("12 3\n\n45 6\n+").unwrap_err(),
+            ParseError::new(3, 3, ParseErrorKind::MissingOperation)
+        );
+        assert_eq!(
+            Worksheet::parse("\n\n").unwrap_err().kind,
+            ParseErrorKind::NoOperations
+        );
+    }
+
+    fn reread(text: &str) -> Homework {
+        Homework::try_from(Worksheet::parse(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_write_puzzle_input() {
+        let input = include_str!("day6-input.txt");
+        let homework = reread(input);
+        let written = homework.write(Orientation::Rows).unwrap();
+        assert_eq!(written, input);
+        assert_eq!(homework.write(Orientation::Vertical), Ok(written));
+
+        // The input mixes both alignments, so forcing one of them can't match
+        assert!(matches!(
+            homework.write(Orientation::LeftAligned),
+            Err(WriteError::Mismatch(_))
+        ));
+        assert!(matches!(
+            homework.write(Orientation::RightAligned),
+            Err(WriteError::Mismatch(_))
+        ));
+
+        let homework = reread(include_str!("day6-test.txt"));
+        assert_eq!(
+            homework.write(Orientation::Rows).unwrap(),
+            "123 328  51 64 \n 45 64  387 23 \n  6 98  215 314\n*   +   *   +  \n"
+        );
+    }
+
+    #[test]
+    fn test_write_random_rows() {
+        let ops = [
+            Operation::Add,
+            Operation::Multiply,
+            Operation::Subtract,
+            Operation::Divide,
+            Operation::Min,
+            Operation::Max,
+            Operation::Power,
+        ];
+        let mut rng = XorShift::new(0x005e_edd6);
+        let mut vertical = 0;
+        for _ in 0..200 {
+            let rows = 1 + rng.next_below(5) as usize;
+            let cols = 1 + rng.next_below(8) as usize;
+            let nums: Vec<Vec<Option<u64>>> = (0..rows)
+                .map(|_| {
+                    (0..cols)
+                        .map(|_| {
+                            let digits = 1 + rng.next_below(6) as u32;
+                            Some(rng.next_below(10u64.pow(digits)))
+                        })
+                        .collect()
+                })
+                .collect();
+            let ops: Vec<Operation> = (0..cols).map(|_| ops[rng.next_below(7) as usize]).collect();
+            let align = if rng.next_below(2) == 0 {
+                Orientation::LeftAligned
+            } else {
+                Orientation::RightAligned
+            };
+
+            // Lay out the numbers by rows, then read them back to get both parts
+            let blocks: Vec<Vec<Vec<char>>> = (0..cols)
+                .map(|c| {
+                    let col: Vec<Option<u64>> = nums.iter().map(|row| row[c]).collect();
+                    layout_rows(&col, align).unwrap()
+                })
+                .collect();
+            let text = Worksheet::join(blocks, &ops).to_string();
+            let homework = reread(&text);
+            assert_eq!(homework.nums_p1, nums);
+            assert_eq!(homework.ops, ops);
+
+            assert_eq!(homework.write(align).unwrap(), text);
+            assert_eq!(
+                reread(&homework.write(Orientation::Rows).unwrap()),
+                homework
+            );
+            // Vertical can only match when every block's part 1 numbers
+            // are what its digits read as hanging from the top or the bottom
+            match homework.write(Orientation::Vertical) {
+                Ok(written) => {
+                    assert_eq!(reread(&written), homework, "{}", text);
+                    vertical += 1;
+                }
+                Err(err) => assert!(
+                    matches!(err, WriteError::Mismatch(_)),
+                    "{:?}\n{}",
+                    err,
+                    text
+                ),
+            }
+        }
+        assert!(vertical > 0);
+    }
+
+    #[test]
+    fn test_write_random_vertical() {
+        let mut rng = XorShift::new(0xcafe_f00d);
+        for _ in 0..200 {
+            let cols = 1 + rng.next_below(6) as usize;
+            // Every block needs at least one number, the rest trail off with None
+            // Each block's numbers get longer or shorter going across so its rows
+            // don't have gaps in the middle
+            let columns: Vec<Vec<u64>> = (0..cols)
+                .map(|_| {
+                    let count = 1 + rng.next_below(4) as usize;
+                    let mut col: Vec<u64> = (0..count).map(|_| 1 + rng.next_below(9999)).collect();
+                    col.sort_by_key(|num| num.to_string().len());
+                    if rng.next_below(2) == 0 {
+                        col.reverse();
+                    }
+                    col
+                })
+                .collect();
+            let max_count = columns.iter().map(Vec::len).max().unwrap();
+            let nums: Vec<Vec<Option<u64>>> = (0..max_count)
+                .map(|n| columns.iter().map(|col| col.get(n).copied()).collect())
+                .collect();
+
+            let height = columns
+                .iter()
+                .flatten()
+                .map(|num| num.to_string().len())
+                .max()
+                .unwrap();
+            let from_top = rng.next_below(2) == 0;
+            let blocks: Vec<Vec<Vec<char>>> = (0..cols)
+                .map(|c| {
+                    let col: Vec<Option<u64>> = nums.iter().map(|row| row[c]).collect();
+                    layout_vertical(&col, height, from_top).unwrap()
+                })
+                .collect();
+            let text = Worksheet::join(blocks, &vec![Operation::Add; cols]).to_string();
+            let homework = reread(&text);
+            assert_eq!(homework.nums_p2, nums);
+
+            let written = homework.write(Orientation::Vertical).unwrap();
+            assert_eq!(written, text);
+            assert_eq!(reread(&written), homework);
+        }
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let homework = Homework {
+            nums_p1: vec![vec![Some(1), None], vec![Some(2), None]],
+            nums_p2: vec![vec![Some(12), None]],
+            ops: vec![Operation::Add, Operation::Add],
+        };
+        assert_eq!(
+            homework.write(Orientation::Rows),
+            Err(WriteError::EmptyBlock(1))
+        );
+
+        let homework = Homework {
+            nums_p1: vec![vec![Some(1)], vec![None]],
+            nums_p2: vec![vec![Some(1)]],
+            ops: vec![Operation::Add],
+        };
+        assert_eq!(
+            homework.write(Orientation::Rows),
+            Err(WriteError::EmptyRow(1))
+        );
+
+        // 10 and 5 right aligned read down as 1 and 05, which can't be written back
+        let homework = Homework {
+            nums_p1: vec![vec![Some(10)], vec![Some(5)]],
+            nums_p2: vec![vec![Some(5)], vec![Some(1)]],
+            ops: vec![Operation::Add],
+        };
+        assert_eq!(
+            homework.write(Orientation::Vertical),
+            Err(WriteError::Mismatch(0))
+        );
+        assert!(homework.write(Orientation::RightAligned).is_ok());
+    }
 }
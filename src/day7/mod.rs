@@ -1,4 +1,7 @@
+use crate::rng::XorShift;
+use std::collections::HashSet;
 use std::str::Lines;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub(crate) fn solve_day7() {
     // Get the input: 2D graph tracking the trachyon beams
@@ -6,70 +9,256 @@ pub(crate) fn solve_day7() {
     // let binding = include_str!("day7-test.txt");
     let lines: Lines<'_> = binding.lines();
 
-    let mut grid: Vec<Vec<char>> = lines.map(|s| s.trim().chars().collect()).collect();
+    let grid: Vec<Vec<char>> = lines.map(|s| s.trim().chars().collect()).collect();
 
-    let splits = track_classical_trachron(&mut grid);
+    // Run the general beam simulator too
+    let report = Manifold::new(&grid).track_beams();
+    println!(
+        "Beam simulator: {} splits, {} energized cells, cycle: {:?}, timelines: {:?}",
+        report.splits, report.energized, report.cycle, report.timelines
+    );
+
+    let splits = track_classical_trachron(&grid);
 
     println!("The trachyon beam splits {} times", splits);
 
-    solve_quantum_trachyon(&grid);
+    match solve_quantum_trachyon(&grid) {
+        Ok(quantum) => {
+            println!("The quantum trachyon has {} timelines", quantum.total);
+            for (c, &count) in quantum.exits.iter().enumerate() {
+                if count > 0 {
+                    println!("  {} timelines leave from column {}", count, c);
+                }
+            }
+
+            for (k, timeline) in quantum.timelines().take(3).enumerate() {
+                println!("Timeline {} goes {:?}", k, timeline.choices);
+            }
+
+            // Pick out a timeline at random and draw it
+            let seed = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |time| time.as_nanos() as u64);
+            let mut rng = XorShift::new(seed);
+            if let Some(timeline) = quantum.sample_timeline(|| rng.next_u64()) {
+                println!("A random timeline goes {:?}", timeline.choices);
+                println!("{}", quantum.render_timeline(&timeline));
+            }
+        }
+        Err(err) => println!("The quantum timelines could not be counted: {:?}", err),
+    }
+
+    // See which splitters matter the most
+    match splitter_sensitivity(&grid) {
+        Ok(sensitivity) => {
+            println!(
+                "Out of {} splits and {} timelines, {} splitters are never reached by a beam",
+                sensitivity.splits,
+                sensitivity.timelines,
+                sensitivity.unreached().count()
+            );
+            if let Some(most) = sensitivity
+                .splitters
+                .iter()
+                .max_by_key(|splitter| splitter.timelines_change.unsigned_abs())
+            {
+                println!(
+                    "Taking out the splitter at ({}, {}) changes the splits by {} and the timelines by {}",
+                    most.row, most.col, most.splits_change, most.timelines_change
+                );
+            }
+        }
+        Err(err) => println!("The splitters could not be checked: {:?}", err),
+    }
+}
+
+/// Number of ways a quantum trachyon particle could leave the grid
+#[derive(Debug)]
+struct QuantumTimelines<'a> {
+    grid: &'a [Vec<char>],
+    /// Number of ways to reach each cell, 0 on splitters and where no beam goes
+    table: Vec<Vec<u128>>,
+    /// Number of timelines leaving the bottom of the grid from each column
+    exits: Vec<u128>,
+    /// Total number of timelines
+    total: u128,
 }
 
 /// Returns the number of possible timelines a quantum trachyon particle could have taken
-fn solve_quantum_trachyon(grid: &Vec<Vec<char>>) -> usize {
+fn solve_quantum_trachyon(grid: &[Vec<char>]) -> Result<QuantumTimelines<'_>, TimelineError> {
     // Work down the grid, at each place counting the number of possible ways to get to that spot
-    // Return the sum of the last numbers in every row
-
-    // Initialize timelines to 2D array of 0s
-    let mut timelines: Vec<Vec<usize>> = Vec::with_capacity(grid.len());
-    for r in 0..grid.len() {
-        timelines.push(vec![0; grid[r].len()]);
-    }
-
-    let mut row_count: usize = 0;
-
-    for (r, filled_row) in grid.iter().enumerate() {
-        // Work down one row at a time from the filled in map
-
-        row_count = 0;
-        for c in 0..filled_row.len() {
-            // Count the number of ways a beam could have gotten to a certain spot
-            // 'S': Base case, 1 start
-            if grid[r][c] == 'S' {
-                timelines[r][c] = 1;
-            } else if grid[r][c] == '|' {
-                // Add the total ways a beam could have gotten to that spot
-                // Number above it + numbers above the splitters on each side
-                let mut count = timelines[r - 1][c];
-                // Left
-                if c as i32 - 1 >= 0 && grid[r][c - 1] == '^' {
-                    count += timelines[r - 1][c - 1];
-                }
-                // Right
-                if c + 1 < grid[r].len() && grid[r][c + 1] == '^' {
-                    count += timelines[r - 1][c + 1];
+    // The last row holds how many timelines leave from each column
+    let mut table: Vec<Vec<u128>> = Vec::with_capacity(grid.len());
+
+    for (r, row) in grid.iter().enumerate() {
+        let above: &[u128] = if r > 0 { &table[r - 1] } else { &[] };
+        let counts: Vec<u128> = (0..row.len())
+            .map(|c| ways_into(row, above, c))
+            .collect::<Result<_, _>>()?;
+        table.push(counts);
+    }
+
+    let exits: Vec<u128> = table.last().cloned().unwrap_or_default();
+    let total = exits
+        .iter()
+        .try_fold(0u128, |total, &count| total.checked_add(count))
+        .ok_or(TimelineError::Overflow)?;
+
+    Ok(QuantumTimelines {
+        grid,
+        table,
+        exits,
+        total,
+    })
+}
+
+/// Counts the number of ways a beam could have gotten to a certain spot, given the counts above it
+fn ways_into(row: &[char], above: &[u128], c: usize) -> Result<u128, TimelineError> {
+    // 'S': Base case, 1 start
+    // '^': Nothing stays on a splitter, it all goes to the sides
+    match row[c] {
+        'S' => return Ok(1),
+        '^' => return Ok(0),
+        _ => {}
+    }
+
+    // Add the total ways a beam could have gotten to that spot
+    // Number above it + numbers above the splitters on each side
+    let mut count = above.get(c).copied().unwrap_or(0);
+    // Left
+    if c > 0 && row[c - 1] == '^' {
+        count = count
+            .checked_add(above.get(c - 1).copied().unwrap_or(0))
+            .ok_or(TimelineError::Overflow)?;
+    }
+    // Right
+    if c + 1 < row.len() && row[c + 1] == '^' {
+        count = count
+            .checked_add(above.get(c + 1).copied().unwrap_or(0))
+            .ok_or(TimelineError::Overflow)?;
+    }
+    Ok(count)
+}
+
+/// One way a quantum trachyon particle could go through the grid
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Timeline {
+    /// Cells the particle passes through, one per row from its source to the bottom
+    path: Vec<(usize, usize)>,
+    /// Which side the particle came out of each splitter it hit, top to bottom
+    choices: Vec<Direction>,
+}
+
+impl<'a> QuantumTimelines<'a> {
+    /// Returns the k-th timeline, counting from 0
+    /// Timelines are ordered by the column they leave from, then working back up the grid
+    /// by whether they came from straight above, the splitter on the left, or the one on the right
+    /// Uses the table to skip over whole groups of timelines, so it only walks one path
+    fn nth_timeline(&self, mut k: u128) -> Option<Timeline> {
+        if k >= self.total {
+            return None;
+        }
+
+        // Find the column this timeline leaves from
+        let mut c: usize = 0;
+        for (col, &count) in self.exits.iter().enumerate() {
+            if k < count {
+                c = col;
+                break;
+            }
+            k -= count;
+        }
+
+        // Work back up to the source, picking the way in that holds the k-th timeline
+        let mut r = self.grid.len() - 1;
+        let mut path: Vec<(usize, usize)> = vec![(r, c)];
+        let mut choices: Vec<Direction> = Vec::new();
+        while self.grid[r][c] != 'S' {
+            let row = &self.grid[r];
+            let above = &self.table[r - 1];
+
+            // Straight down, out the right of a splitter on the left, out the left of one on the right
+            let mut ways: Vec<(usize, Option<Direction>)> = vec![(c, None)];
+            if c > 0 && row[c - 1] == '^' {
+                ways.push((c - 1, Some(Direction::Right)));
+            }
+            if c + 1 < row.len() && row[c + 1] == '^' {
+                ways.push((c + 1, Some(Direction::Left)));
+            }
+
+            for (from, choice) in ways {
+                let count = above.get(from).copied().unwrap_or(0);
+                if k < count {
+                    c = from;
+                    choices.extend(choice);
+                    break;
                 }
-                timelines[r][c] = count;
-                row_count += count;
+                k -= count;
             }
+
+            r -= 1;
+            path.push((r, c));
+        }
+
+        path.reverse();
+        choices.reverse();
+        Some(Timeline { path, choices })
+    }
+
+    /// Goes through every timeline in order, working each one out as it is needed
+    fn timelines(&self) -> impl Iterator<Item = Timeline> + '_ {
+        (0..self.total).filter_map(|k| self.nth_timeline(k))
+    }
+
+    /// Picks a timeline with every one equally likely
+    /// Takes random u64s from the given source, throwing away any draws that would favor
+    /// the first timelines
+    fn sample_timeline(&self, mut random: impl FnMut() -> u64) -> Option<Timeline> {
+        if self.total == 0 {
+            return None;
         }
 
-        println!("Row {} contains {} paths", r, row_count);
+        // Only accept draws below the largest multiple of the total that fits in a u128
+        let leftover = (u128::MAX % self.total + 1) % self.total;
+        loop {
+            let draw = ((random() as u128) << 64) | random() as u128;
+            if draw <= u128::MAX - leftover {
+                return self.nth_timeline(draw % self.total);
+            }
+        }
     }
 
-    row_count
+    /// Draws the path of the timeline over the grid
+    fn render_timeline(&self, timeline: &Timeline) -> String {
+        let mut grid: Vec<Vec<char>> = self.grid.to_vec();
+        for &(r, c) in &timeline.path {
+            if grid[r][c] == '.' {
+                grid[r][c] = '|';
+            }
+        }
+
+        grid.iter()
+            .map(|row| row.iter().collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
 }
 
-/// Tracks a trachyon beam down the grid
+/// Tracks a trachyon beam down the grid without changing it
 /// Returns the number of splits
-fn track_classical_trachron(grid: &mut Vec<Vec<char>>) -> usize {
+fn track_classical_trachron(grid: &[Vec<char>]) -> usize {
     // Index bounds
     let rows = grid.len();
-    let cols = grid[0].len();
 
     // Tracking number of times a beam gets split
     let mut splits: usize = 0;
 
+    // Which cells the beam has reached so far
+    let mut beams: Vec<Vec<bool>> = grid
+        .iter()
+        .map(|row| row.iter().map(|&char| char == 'S').collect())
+        .collect();
+
     // Notes:
     // There are no splitters on the grid column borders
     // There are no side-by-side splitters
@@ -77,22 +266,802 @@ fn track_classical_trachron(grid: &mut Vec<Vec<char>>) -> usize {
     // Track the beams down the grid
     for r in 1..rows {
         // Iterate through each row (starting with the second) to see beam travels
-        for c in 0..cols {
+        for c in 0..grid[r].len() {
+            let beam_above = beams[r - 1].get(c).copied().unwrap_or(false);
             // Two rules for beam travel:
             // 1. If empty space '.', any beam (or source 'S') above travels down to this space
-            if grid[r][c] == '.' && (grid[r - 1][c] == '|' || grid[r - 1][c] == 'S') {
-                grid[r][c] = '|';
+            if grid[r][c] == '.' && beam_above {
+                beams[r][c] = true;
             }
 
             // 2. If splitter '^', any beam above gets placed on right and left of the splitter
             // Update the split counter
-            if grid[r][c] == '^' && grid[r - 1][c] == '|' {
+            if grid[r][c] == '^' && beam_above {
                 splits += 1;
-                grid[r][c - 1] = '|';
-                grid[r][c + 1] = '|';
+                beams[r][c - 1] = true;
+                beams[r][c + 1] = true;
             }
         }
     }
 
     splits
 }
+
+/// What would happen if a single splitter were taken out of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SplitterSensitivity {
+    row: usize,
+    col: usize,
+    /// Whether a classical beam ever comes down onto the splitter
+    reached: bool,
+    /// How the number of classical splits changes without the splitter
+    splits_change: isize,
+    /// How the number of quantum timelines changes without the splitter
+    timelines_change: i128,
+}
+
+/// How much each splitter matters to the beams going through the grid
+#[derive(Debug)]
+struct SensitivityReport {
+    splits: usize,
+    timelines: u128,
+    /// One entry per splitter, top to bottom then left to right
+    splitters: Vec<SplitterSensitivity>,
+}
+
+impl SensitivityReport {
+    /// Splitters that no beam ever gets to
+    fn unreached(&self) -> impl Iterator<Item = &SplitterSensitivity> {
+        self.splitters.iter().filter(|splitter| !splitter.reached)
+    }
+}
+
+/// Works out what taking out each splitter would do to the classical splits and quantum timelines
+///
+/// Taking out a splitter only changes the row it is on, so both counts can be found from
+/// what reaches that row going down (forward pass) and what each cell leads to further
+/// down (backward pass), without running the whole grid again for every splitter
+///
+/// Classical: the splitters below a row that get hit are the ones each lit cell of the row
+/// leads to, plus the ones any lower sources lead to. These are kept as bit sets per cell
+/// Quantum: the number of timelines is linear in the counts on any row, so each cell has a
+/// weight (the number of ways out from there) and the change is the new counts on the
+/// three cells around the splitter times their weights
+fn splitter_sensitivity(grid: &[Vec<char>]) -> Result<SensitivityReport, TimelineError> {
+    let rows = grid.len();
+
+    // Number every splitter
+    let mut ids: Vec<Vec<Option<usize>>> = Vec::with_capacity(rows);
+    let mut splitters: Vec<(usize, usize)> = Vec::new();
+    for (r, row) in grid.iter().enumerate() {
+        ids.push(
+            row.iter()
+                .enumerate()
+                .map(|(c, &char)| {
+                    (char == '^').then(|| {
+                        splitters.push((r, c));
+                        splitters.len() - 1
+                    })
+                })
+                .collect(),
+        );
+    }
+    let words = splitters.len().div_ceil(64);
+
+    // Forward: which cells the classical beam reaches, and how many splitters get hit on each row
+    let mut beams: Vec<Vec<bool>> = Vec::with_capacity(rows);
+    let mut hits_to: Vec<usize> = Vec::with_capacity(rows);
+    for (r, row) in grid.iter().enumerate() {
+        let above: &[bool] = if r > 0 { &beams[r - 1] } else { &[] };
+        let hits = (0..row.len())
+            .filter(|&c| row[c] == '^' && above.get(c).copied().unwrap_or(false))
+            .count();
+        hits_to.push(hits_to.last().copied().unwrap_or(0) + hits);
+        beams.push((0..row.len()).map(|c| beam_into(row, above, c)).collect());
+    }
+    let splits = hits_to.last().copied().unwrap_or(0);
+
+    // Forward: the number of ways to reach each cell
+    let quantum = solve_quantum_trachyon(grid)?;
+    let timelines = quantum.total;
+    let table = &quantum.table;
+
+    // Fills in the sensitivity for each splitter on a row once the backward pass gets there
+    let mut report: Vec<Option<SplitterSensitivity>> = vec![None; splitters.len()];
+
+    // Backward: the splitters hit below each cell if it is lit, and the number of ways out from it
+    let mut below: Vec<Vec<u64>> = Vec::new();
+    let mut weights_below: Vec<u128> = Vec::new();
+    // Splitters hit from sources lower down, which stay lit whatever happens above them
+    let mut from_sources: Vec<u64> = vec![0; words];
+
+    for r in (0..rows).rev() {
+        let row = &grid[r];
+        let next = grid.get(r + 1);
+
+        let mut hit_sets: Vec<Vec<u64>> = Vec::with_capacity(row.len());
+        let mut weights: Vec<u128> = Vec::with_capacity(row.len());
+        for c in 0..row.len() {
+            let Some(next) = next else {
+                hit_sets.push(vec![0; words]);
+                weights.push(1);
+                continue;
+            };
+
+            // A beam carries on into anything below it except a source or splitter
+            let carries_on = |c: usize| next.get(c).is_some_and(|&char| char != '^' && char != 'S');
+
+            let mut hit = vec![0; words];
+            let mut weight: u128 = 0;
+            match next.get(c) {
+                Some('^') => {
+                    let id = ids[r + 1][c].unwrap_or_default();
+                    hit[id / 64] |= 1 << (id % 64);
+                    // Classical beams land beside the splitter whatever is there
+                    for side in [c.checked_sub(1), Some(c + 1)].into_iter().flatten() {
+                        if let Some(set) = below.get(side) {
+                            union_with(&mut hit, set);
+                        }
+                        if carries_on(side) {
+                            weight = weight
+                                .checked_add(weights_below[side])
+                                .ok_or(TimelineError::Overflow)?;
+                        }
+                    }
+                }
+                Some('S') | None => {}
+                Some(&char) => {
+                    if char == '.' {
+                        union_with(&mut hit, &below[c]);
+                    }
+                    weight = weights_below[c];
+                }
+            }
+            hit_sets.push(hit);
+            weights.push(weight);
+        }
+
+        // Take out each splitter on this row in turn
+        for (s, _) in row.iter().enumerate().filter(|&(_, &char)| char == '^') {
+            let above_beams: &[bool] = if r > 0 { &beams[r - 1] } else { &[] };
+            let above_ways: &[u128] = if r > 0 { &table[r - 1] } else { &[] };
+            let reached = above_beams.get(s).copied().unwrap_or(false);
+            let mut without = row.clone();
+            without[s] = '.';
+
+            // Splitters hit on rows up to this one stay the same, apart from this one
+            let mut hit = from_sources.clone();
+            for (c, set) in hit_sets.iter().enumerate() {
+                if beam_into(&without, above_beams, c) {
+                    union_with(&mut hit, set);
+                }
+            }
+            let hits_above = hits_to[r] - usize::from(reached);
+            let splits_without =
+                hits_above + hit.iter().map(|w| w.count_ones() as usize).sum::<usize>();
+
+            // Only the counts beside and on the splitter change
+            let mut old_part: u128 = 0;
+            let mut new_part: u128 = 0;
+            for c in s.saturating_sub(1)..(s + 2).min(row.len()) {
+                let old = table[r][c]
+                    .checked_mul(weights[c])
+                    .ok_or(TimelineError::Overflow)?;
+                let new = ways_into(&without, above_ways, c)?
+                    .checked_mul(weights[c])
+                    .ok_or(TimelineError::Overflow)?;
+                old_part = old_part.checked_add(old).ok_or(TimelineError::Overflow)?;
+                new_part = new_part.checked_add(new).ok_or(TimelineError::Overflow)?;
+            }
+            let timelines_without = (timelines - old_part)
+                .checked_add(new_part)
+                .ok_or(TimelineError::Overflow)?;
+            let signed = |n: u128| i128::try_from(n).map_err(|_| TimelineError::Overflow);
+
+            let id = ids[r][s].unwrap_or_default();
+            report[id] = Some(SplitterSensitivity {
+                row: r,
+                col: s,
+                reached,
+                splits_change: splits_without as isize - splits as isize,
+                timelines_change: signed(timelines_without)? - signed(timelines)?,
+            });
+        }
+
+        // Sources on this row are always lit for the rows above
+        for (c, _) in row.iter().enumerate().filter(|&(_, &char)| char == 'S') {
+            union_with(&mut from_sources, &hit_sets[c]);
+        }
+
+        below = hit_sets;
+        weights_below = weights;
+    }
+
+    Ok(SensitivityReport {
+        splits,
+        timelines,
+        splitters: report.into_iter().flatten().collect(),
+    })
+}
+
+/// Whether a classical beam gets to a certain spot, given which cells above it are lit
+/// Follows the same rules as track_classical_trachron
+fn beam_into(row: &[char], above: &[bool], c: usize) -> bool {
+    let lit = |c: usize| above.get(c).copied().unwrap_or(false);
+    row[c] == 'S'
+        || (row[c] == '.' && lit(c))
+        || (c > 0 && row[c - 1] == '^' && lit(c - 1))
+        || (c + 1 < row.len() && row[c + 1] == '^' && lit(c + 1))
+}
+
+/// Adds every splitter in the other bit set to this one
+fn union_with(set: &mut [u64], other: &[u64]) {
+    for (word, other) in set.iter_mut().zip(other) {
+        *word |= other;
+    }
+}
+
+/// Which way a beam is travelling across the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn is_vertical(&self) -> bool {
+        matches!(self, Direction::Up | Direction::Down)
+    }
+
+    /// Where a mirror sends the beam
+    ///   /     \
+    /// Down -> Left for '/', Down -> Right for '\'
+    fn reflect(&self, mirror: char) -> Direction {
+        match (mirror, self) {
+            ('/', Direction::Down) | ('\\', Direction::Up) => Direction::Left,
+            ('/', Direction::Up) | ('\\', Direction::Down) => Direction::Right,
+            ('/', Direction::Left) | ('\\', Direction::Right) => Direction::Down,
+            ('/', Direction::Right) | ('\\', Direction::Left) => Direction::Up,
+            _ => panic!("Not a mirror: {}", mirror),
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        }
+    }
+}
+
+/// A beam entering a cell of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Beam {
+    row: usize,
+    col: usize,
+    dir: Direction,
+}
+
+/// What happens to a beam once it enters a cell
+#[derive(Debug)]
+struct Interaction {
+    /// Beams that leave the cell and enter another one on the grid
+    next: Vec<Beam>,
+    /// Number of beams that leave the grid or get absorbed here
+    ends: usize,
+    /// Whether the beam was split in two
+    split: bool,
+}
+
+/// Why the number of quantum timelines could not be counted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimelineError {
+    /// A beam comes back round to where it has been, so there are endless timelines
+    Cycle(Beam),
+    /// More timelines than fit in a u128
+    Overflow,
+}
+
+/// Everything found by following the beams through the manifold
+#[derive(Debug)]
+struct BeamReport {
+    /// Number of times a splitter splits a beam, counting each splitter once per direction
+    splits: usize,
+    /// Number of cells any beam passes through
+    energized: usize,
+    /// A beam which comes back round to itself, if there are any
+    cycle: Option<Beam>,
+    /// Number of ways a single quantum particle from each source could go, added together
+    timelines: Result<u128, TimelineError>,
+}
+
+/// General trachyon manifold
+/// 'S' Source, sends a beam downwards
+/// '.' Empty space, beams pass straight through
+/// '^' Splitter, a beam travelling up or down carries on from both cells beside it
+///     A splitter right beside it is slipped past, anything else acts on the beam
+///     Beams coming in from the side are stopped by it
+/// '/' '\' Mirrors, turn the beam 90 degrees
+/// '#' Absorber, stops any beam
+struct Manifold<'a> {
+    grid: &'a [Vec<char>],
+    /// Length of the longest row, so every cell has a spot in a flat list
+    cols: usize,
+}
+
+impl<'a> Manifold<'a> {
+    fn new(grid: &'a [Vec<char>]) -> Self {
+        let cols = grid.iter().map(Vec::len).max().unwrap_or(0);
+        Self { grid, cols }
+    }
+
+    /// Returns the cell next to this one in the direction, if it is on the grid
+    fn step(&self, row: usize, col: usize, dir: Direction) -> Option<Beam> {
+        let (row, col) = match dir {
+            Direction::Up => (row.checked_sub(1)?, col),
+            Direction::Down => (row + 1, col),
+            Direction::Left => (row, col.checked_sub(1)?),
+            Direction::Right => (row, col + 1),
+        };
+        (col < self.grid.get(row)?.len()).then_some(Beam { row, col, dir })
+    }
+
+    /// Beams start on top of each source heading down
+    fn sources(&self) -> Vec<Beam> {
+        let mut sources: Vec<Beam> = Vec::new();
+        for (row, line) in self.grid.iter().enumerate() {
+            for (col, &char) in line.iter().enumerate() {
+                if char == 'S' {
+                    sources.push(Beam {
+                        row,
+                        col,
+                        dir: Direction::Down,
+                    });
+                }
+            }
+        }
+        sources
+    }
+
+    /// Works out where the beam goes after entering its cell
+    fn interact(&self, beam: Beam) -> Interaction {
+        // Each outgoing beam either lands on the grid or ends
+        let mut next: Vec<Beam> = Vec::with_capacity(2);
+        let mut ends: usize = 0;
+        let mut send = |out: Option<Beam>| match out {
+            Some(out) => next.push(out),
+            None => ends += 1,
+        };
+
+        let mut split = false;
+        match self.grid[beam.row][beam.col] {
+            '#' => send(None),
+            '^' if beam.dir.is_vertical() => {
+                // Carry on in the same direction from the cells either side
+                split = true;
+                for side in [Direction::Left, Direction::Right] {
+                    send(self.step(beam.row, beam.col, side).and_then(|side| {
+                        if self.grid[side.row][side.col] == '^' {
+                            // Slip past a splitter right next to this one
+                            self.step(side.row, side.col, beam.dir)
+                        } else {
+                            Some(Beam {
+                                dir: beam.dir,
+                                ..side
+                            })
+                        }
+                    }));
+                }
+            }
+            '^' => send(None),
+            mirror @ ('/' | '\\') => {
+                send(self.step(beam.row, beam.col, beam.dir.reflect(mirror)));
+            }
+            _ => send(self.step(beam.row, beam.col, beam.dir)),
+        }
+
+        Interaction { next, ends, split }
+    }
+
+    /// Index of the beam into a flat list of every cell and direction
+    fn state_index(&self, beam: &Beam) -> usize {
+        (beam.row * self.cols + beam.col) * 4 + beam.dir.index()
+    }
+
+    /// Follows every beam from every source through the manifold
+    /// Depth first, so a beam reaching one that is still being followed means a cycle
+    /// Each beam's number of timelines is the sum of the beams it turns into,
+    /// plus one for each that leaves the grid or gets absorbed
+    fn track_beams(&self) -> BeamReport {
+        #[derive(Clone, Copy)]
+        enum Visit {
+            Unseen,
+            InProgress,
+            Done(u128),
+        }
+
+        let mut visits: Vec<Visit> = vec![Visit::Unseen; self.grid.len() * self.cols * 4];
+        let mut energized: HashSet<(usize, usize)> = HashSet::new();
+        let mut splits: usize = 0;
+        let mut cycle: Option<Beam> = None;
+        let mut overflow = false;
+        let mut timelines: u128 = 0;
+
+        for source in self.sources() {
+            if let Visit::Done(count) = visits[self.state_index(&source)] {
+                timelines = timelines.checked_add(count).unwrap_or_else(|| {
+                    overflow = true;
+                    u128::MAX
+                });
+                continue;
+            }
+
+            // Each entry is a beam, what it turns into and how many of those are followed
+            let mut stack: Vec<(Beam, Interaction, usize)> = Vec::new();
+            visits[self.state_index(&source)] = Visit::InProgress;
+            stack.push((source, self.interact(source), 0));
+
+            while let Some((beam, interaction, followed)) = stack.last_mut() {
+                if let Some(&next) = interaction.next.get(*followed) {
+                    *followed += 1;
+                    match visits[self.state_index(&next)] {
+                        Visit::Unseen => {
+                            visits[self.state_index(&next)] = Visit::InProgress;
+                            let next_interaction = self.interact(next);
+                            stack.push((next, next_interaction, 0));
+                        }
+                        Visit::InProgress => {
+                            cycle.get_or_insert(next);
+                        }
+                        Visit::Done(_) => {}
+                    }
+                    continue;
+                }
+
+                // Everything this beam turns into has been followed
+                let beam = *beam;
+                energized.insert((beam.row, beam.col));
+                if interaction.split {
+                    splits += 1;
+                }
+                let mut count = interaction.ends as u128;
+                for next in &interaction.next {
+                    // Beams still in progress are part of a cycle, the count won't be used
+                    if let Visit::Done(next_count) = visits[self.state_index(next)] {
+                        count = count.checked_add(next_count).unwrap_or_else(|| {
+                            overflow = true;
+                            u128::MAX
+                        });
+                    }
+                }
+                visits[self.state_index(&beam)] = Visit::Done(count);
+                stack.pop();
+
+                if stack.is_empty() {
+                    timelines = timelines.checked_add(count).unwrap_or_else(|| {
+                        overflow = true;
+                        u128::MAX
+                    });
+                }
+            }
+        }
+
+        let timelines = match (cycle, overflow) {
+            (Some(beam), _) => Err(TimelineError::Cycle(beam)),
+            (None, true) => Err(TimelineError::Overflow),
+            (None, false) => Ok(timelines),
+        };
+
+        BeamReport {
+            splits,
+            energized: energized.len(),
+            cycle,
+            timelines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn to_grid(input: &str) -> Vec<Vec<char>> {
+        input.lines().map(|s| s.trim().chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_matches_down_only_solution() {
+        for input in [
+            include_str!("day7-test.txt"),
+            include_str!("day7-input.txt"),
+        ] {
+            let grid = to_grid(input);
+            let report = Manifold::new(&grid).track_beams();
+            assert_eq!(report.cycle, None);
+
+            let splits = track_classical_trachron(&grid);
+            let quantum = solve_quantum_trachyon(&grid).unwrap();
+            assert_eq!(report.splits, splits);
+            assert_eq!(report.timelines, Ok(quantum.total));
+        }
+
+        let report = Manifold::new(&to_grid(include_str!("day7-test.txt"))).track_beams();
+        assert_eq!(report.splits, 21);
+        assert_eq!(report.timelines, Ok(40));
+    }
+
+    #[test]
+    fn test_quantum_from_raw_map() {
+        let grid = to_grid(include_str!("day7-test.txt"));
+        assert_eq!(track_classical_trachron(&grid), 21);
+        // Neither phase touches the map, so they can run in any order
+        assert_eq!(grid, to_grid(include_str!("day7-test.txt")));
+
+        let quantum = solve_quantum_trachyon(&grid).unwrap();
+        assert_eq!(quantum.total, 40);
+        assert_eq!(
+            quantum.exits,
+            vec![1, 0, 2, 0, 10, 0, 11, 0, 11, 0, 2, 1, 1, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_enumerate_timelines() {
+        let grid = to_grid(include_str!("day7-test.txt"));
+        let quantum = solve_quantum_trachyon(&grid).unwrap();
+
+        let timelines: Vec<Timeline> = quantum.timelines().collect();
+        assert_eq!(timelines.len(), 40);
+        let distinct: HashSet<Vec<Direction>> =
+            timelines.iter().map(|t| t.choices.clone()).collect();
+        assert_eq!(distinct.len(), 40);
+
+        for timeline in &timelines {
+            // One cell per row from the source down, stepping at most one column
+            assert_eq!(timeline.path.len(), grid.len());
+            assert_eq!(grid[timeline.path[0].0][timeline.path[0].1], 'S');
+            let mut choices = timeline.choices.iter();
+            for pair in timeline.path.windows(2) {
+                let ((_, c1), (r2, c2)) = (pair[0], pair[1]);
+                assert_ne!(grid[r2][c2], '^');
+                if c1 != c2 {
+                    assert_eq!(grid[r2][c1], '^');
+                    let went = if c2 < c1 {
+                        Direction::Left
+                    } else {
+                        Direction::Right
+                    };
+                    assert_eq!(choices.next(), Some(&went));
+                } else {
+                    assert_ne!(grid[r2][c1], '^');
+                }
+            }
+            assert_eq!(choices.next(), None);
+        }
+
+        // First timeline leaves from the far left, always going left
+        assert_eq!(timelines[0].choices, vec![Direction::Left; 7]);
+        assert_eq!(timelines[39].choices.last(), Some(&Direction::Right));
+        assert_eq!(quantum.nth_timeline(40), None);
+
+        let mut rng = XorShift::new(0x0007_0007);
+        for _ in 0..20 {
+            let sample = quantum.sample_timeline(|| rng.next_u64()).unwrap();
+            assert!(timelines.contains(&sample));
+        }
+
+        assert_eq!(
+            quantum.render_timeline(&timelines[0]),
+            to_grid(include_str!("day7-test.txt"))
+                .iter()
+                .enumerate()
+                .map(|(r, row)| {
+                    // The leftmost timeline moves one column left every two rows
+                    let c = 7 - r / 2;
+                    let mut row = row.clone();
+                    if row[c] == '.' {
+                        row[c] = '|';
+                    }
+                    row.into_iter().collect::<String>()
+                })
+                .collect::<Vec<String>>()
+                .join("\n")
+        );
+    }
+
+    /// Takes out each splitter in turn and runs the whole grid again
+    fn check_sensitivity(grid: &[Vec<char>]) {
+        let report = splitter_sensitivity(grid).unwrap();
+        assert_eq!(report.splits, track_classical_trachron(grid));
+        assert_eq!(
+            report.timelines,
+            solve_quantum_trachyon(grid).unwrap().total
+        );
+
+        let splitters: Vec<(usize, usize)> = grid
+            .iter()
+            .enumerate()
+            .flat_map(|(r, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|&(_, &char)| char == '^')
+                    .map(move |(c, _)| (r, c))
+            })
+            .collect();
+        assert_eq!(report.splitters.len(), splitters.len());
+
+        for (splitter, &(r, c)) in report.splitters.iter().zip(&splitters) {
+            assert_eq!((splitter.row, splitter.col), (r, c));
+            let mut without = grid.to_vec();
+            without[r][c] = '.';
+            let splits = track_classical_trachron(&without) as isize;
+            let timelines = solve_quantum_trachyon(&without).unwrap().total as i128;
+            assert_eq!(
+                splitter.splits_change,
+                splits - report.splits as isize,
+                "splits without ({}, {})",
+                r,
+                c
+            );
+            assert_eq!(
+                splitter.timelines_change,
+                timelines - report.timelines as i128,
+                "timelines without ({}, {})",
+                r,
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn test_splitter_sensitivity() {
+        check_sensitivity(&to_grid(include_str!("day7-test.txt")));
+        check_sensitivity(&to_grid(include_str!("day7-input.txt")));
+
+        // Splitters next to each other, a late source, and splitters nothing reaches
+        let grid = to_grid(
+            r"..S......
+              .........
+              .^^....^.
+              .........
+              ..^.S^...
+              .........
+              .^.^..^..
+              .........",
+        );
+        check_sensitivity(&grid);
+
+        let report = splitter_sensitivity(&grid).unwrap();
+        let unreached: Vec<(usize, usize)> = report.unreached().map(|s| (s.row, s.col)).collect();
+        // The splitter at (2, 1) only has a beam land on it from the side
+        assert_eq!(unreached, vec![(2, 1), (2, 7), (4, 2), (4, 5), (6, 6)]);
+        // Out of the way of every beam, so they make no difference
+        for splitter in report.unreached().filter(|s| s.col > 5) {
+            assert_eq!((splitter.splits_change, splitter.timelines_change), (0, 0));
+        }
+        // Swallows the left half of the splitter beside it, which would split again further down
+        assert_eq!(report.splitters[0].timelines_change, 2);
+    }
+
+    #[test]
+    fn test_quantum_overflow() {
+        // Stack up enough splitters in a triangle for the middle column to pass 2^128
+        let width = 2 * 140 + 1;
+        let mut grid: Vec<Vec<char>> = vec![vec!['.'; width]];
+        grid[0][width / 2] = 'S';
+        for level in 0..140 {
+            let mut row = vec!['.'; width];
+            for c in (width / 2 - level..=width / 2 + level).step_by(2) {
+                row[c] = '^';
+            }
+            grid.push(row);
+            grid.push(vec!['.'; width]);
+        }
+        assert_eq!(
+            solve_quantum_trachyon(&grid).unwrap_err(),
+            TimelineError::Overflow
+        );
+        assert_eq!(
+            solve_quantum_trachyon(&grid[..100]).unwrap().total,
+            2u128.pow(50)
+        );
+    }
+
+    #[test]
+    fn test_mirrors_and_absorbers() {
+        // Down, right along the mirror row, and back down onto side-by-side splitters
+        // The left half is absorbed, the right half slips past the other splitter
+        let grid = to_grid(
+            r".S....
+             .\.\..
+             ...^^.
+             ..#...",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.cycle, None);
+        assert_eq!(report.splits, 1);
+        assert_eq!(report.timelines, Ok(2));
+
+        // Sideways beams are stopped by a splitter
+        let grid = to_grid(
+            r".S...
+             .\.^.
+             .....",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.splits, 0);
+        assert_eq!(report.energized, 4);
+        assert_eq!(report.timelines, Ok(1));
+
+        // Down into '/' and out the left side of the grid
+        let grid = to_grid(
+            r"S...
+             /.\.
+             .^..
+             ....",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.energized, 2);
+        assert_eq!(report.timelines, Ok(1));
+    }
+
+    #[test]
+    fn test_multiple_sources() {
+        // Both sources go straight down between the splitters
+        let grid = to_grid(
+            r"S...S
+             .....
+             .^.^.
+             .....",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.splits, 0);
+        assert_eq!(report.timelines, Ok(2));
+
+        // Each source splits once, the two inner halves share a path
+        let grid = to_grid(
+            r".S.S.
+             .....
+             .^.^.
+             .....",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.splits, 2);
+        assert_eq!(report.energized, 12);
+        assert_eq!(report.timelines, Ok(4));
+    }
+
+    #[test]
+    fn test_mirror_loop() {
+        // The left half of the split goes round the mirrors and back to where it started
+        let grid = to_grid(
+            r"..S..
+             ./..\
+             ..^..
+             .\../
+             .....",
+        );
+        let report = Manifold::new(&grid).track_beams();
+        assert_eq!(report.splits, 1);
+        assert_eq!(
+            report.cycle,
+            Some(Beam {
+                row: 2,
+                col: 1,
+                dir: Direction::Down
+            })
+        );
+        assert_eq!(
+            report.timelines,
+            Err(TimelineError::Cycle(Beam {
+                row: 2,
+                col: 1,
+                dir: Direction::Down
+            }))
+        );
+    }
+}
@@ -1,21 +1,84 @@
+use crate::settings::{output_path, setting, write_output};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 pub(crate) fn solve_day8() {
     // Get the input: list of 3D coordinates of junction boxes to connect
     let (input, number) = (include_str!("day8-input.txt"), 1000);
     // let (input, number) = (include_str!("day8-test.txt"), 10);
 
-    let mut boxes: Vec<JunctionBox> = parse_input(input);
+    let boxes: Vec<JunctionBox> = parse_input(input);
     // print_boxes(&boxes);
 
-    let connections: Vec<Connection> = form_connections(&boxes);
-    // print_connections(&connections);
+    // Only work out connections as they are needed, shortest first
+    let connections = NearestPairs::new(&boxes);
+    // print_connections(&_form_connections(&boxes));
+
+    let report = track_circuits(&boxes, connections, number);
 
-    let circuits = track_circuits(&mut boxes, &connections, number);
-    // println!("Circuits: {:?}", circuits);
+    match report.top_three {
+        Ok(product) => println!(
+            "Top three circuit sizes after {} connections: {}",
+            number, product
+        ),
+        Err(TopThreeError::NotEnoughConnections { made }) => {
+            println!("Only {} connections could be made, not {}", made, number)
+        }
+        Err(TopThreeError::FewerThanThreeCircuits(count)) => {
+            println!("Only {} circuits after {} connections", count, number)
+        }
+    }
+    match report.final_connection {
+        Some(last) => {
+            println!(
+                "Connection #{} joins every box: {:?} and {:?}",
+                last.connection_idx, last.box1, last.box2
+            );
+            println!(
+                "Product of x coords: {} * {} = {}",
+                last.box1.x, last.box2.x, last.x_product
+            );
+        }
+        None => println!("The boxes never make one circuit"),
+    }
 
-    evaluate_circuits(&circuits, boxes.len());
+    // Join everything up with the shortest total length of connection
+    let tree = spanning_tree(boxes.len(), NearestPairs::new(&boxes));
+    println!(
+        "Minimum spanning tree: {} connections, total length {:.3}",
+        tree.merges.len(),
+        tree.total_length()
+    );
+    for k in [1, 3, 10] {
+        if let Some(threshold) = tree.threshold_for(k) {
+            println!(
+                "Connecting boxes up to {:.3} apart leaves {} circuits",
+                (threshold as f64).sqrt(),
+                tree.clusters_within(threshold)
+            );
+        }
+    }
+    // Export the network to look at in a 3D viewer, after DAY8_EXPORT_CONNECTIONS connections
+    if let (Some(ply_path), Some(dot_path)) = (output_path("day8.ply"), output_path("day8.dot")) {
+        let count = setting("DAY8_EXPORT_CONNECTIONS", number);
+        let snapshot = CircuitSnapshot::new(&boxes, NearestPairs::new(&boxes), count);
+        println!(
+            "Exporting {} boxes and {} connections",
+            boxes.len(),
+            snapshot.connections.len()
+        );
+        write_output(ply_path, snapshot.write_ply());
+        write_output(dot_path, snapshot.write_dot());
+    }
+
+    let dendrogram = tree.write_dendrogram();
+    println!("First merges of the dendrogram (cluster, cluster, distance, size):");
+    for line in dendrogram.lines().take(5) {
+        println!("  {}", line);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct JunctionBox {
     // id: usize, // ID (initial index) of this box
     x: i64,
@@ -24,10 +87,9 @@ struct JunctionBox {
     // shortest_id: usize,          // ID of the shortest connection to this box
     // dist: u64,                   // Distance to the shortest ID
     // connected_to: Option<usize>, // ID of the final connection made
-    circuit_num: Option<usize>, // Number of the circuit they're a part of
 }
 
-#[derive(Debug, PartialEq, Eq, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord)]
 struct Connection {
     dist: u64,
     box1_idx: usize,
@@ -48,156 +110,397 @@ impl JunctionBox {
     }
 }
 
+/// Which circuit each box is in, as a disjoint set forest
+/// Each circuit is a tree of boxes pointing towards one root box that stands for the circuit
+///
+/// ```text
+///   3 <- 0 <- 5     Circuit of 3: {0, 3, 5}, size 3
+///   1 <- 4          Circuit of 1: {1, 4}, size 2
+///   2               Circuit of 2: {2}, size 1
+/// ```
+#[derive(Debug, Clone)]
+struct Circuits {
+    /// Next box towards the root of each box's circuit
+    parent: Vec<usize>,
+    /// Number of boxes in each circuit, only kept up to date on the roots
+    size: Vec<usize>,
+    /// Number of circuits, counting lone boxes
+    count: usize,
+}
+
+impl Circuits {
+    /// Starts with every box in a circuit of its own
+    fn new(boxes: usize) -> Self {
+        Circuits {
+            parent: (0..boxes).collect(),
+            size: vec![1; boxes],
+            count: boxes,
+        }
+    }
+
+    /// Returns the root box of the box's circuit
+    /// Points everything passed on the way straight at the root, so later lookups are quicker
+    fn find(&mut self, idx: usize) -> usize {
+        let mut root = idx;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+
+        let mut idx = idx;
+        while self.parent[idx] != root {
+            let next = self.parent[idx];
+            self.parent[idx] = root;
+            idx = next;
+        }
+
+        root
+    }
+
+    /// Connects the two boxes, merging the smaller circuit into the larger one
+    /// Returns false if they were already in the same circuit
+    fn connect(&mut self, idx1: usize, idx2: usize) -> bool {
+        let (mut root1, mut root2) = (self.find(idx1), self.find(idx2));
+        if root1 == root2 {
+            return false;
+        }
+
+        if self.size[root1] < self.size[root2] {
+            std::mem::swap(&mut root1, &mut root2);
+        }
+        self.parent[root2] = root1;
+        self.size[root1] += self.size[root2];
+        self.count -= 1;
+
+        true
+    }
+
+    /// Sizes of every circuit, including lone boxes
+    fn sizes(&self) -> Vec<usize> {
+        (0..self.parent.len())
+            .filter(|&idx| self.parent[idx] == idx)
+            .map(|idx| self.size[idx])
+            .collect()
+    }
+}
+
+/// The connection that first puts every box into one circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FinalConnection {
+    /// Position of the connection in the sorted list
+    connection_idx: usize,
+    box1_idx: usize,
+    box2_idx: usize,
+    box1: JunctionBox,
+    box2: JunctionBox,
+    /// Product of the two boxes' x coordinates
+    x_product: i64,
+}
+
+/// Why the three largest circuits can't be multiplied together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TopThreeError {
+    /// The connections ran out before the target number were made
+    NotEnoughConnections { made: usize },
+    /// The boxes are already joined up into this many circuits
+    FewerThanThreeCircuits(usize),
+}
+
+/// Answers from connecting up the boxes
+#[derive(Debug)]
+struct CircuitReport {
+    /// Product of the three largest circuit sizes after the target number of connections
+    top_three: Result<usize, TopThreeError>,
+    /// None if the connections run out before every box is in one circuit
+    final_connection: Option<FinalConnection>,
+}
+
 /// Form circuits from connected boxes
+/// Keeps connecting until both the target number of connections is made and every box
+/// is in one circuit
 fn track_circuits(
-    boxes: &mut Vec<JunctionBox>,
-    connections: &Vec<Connection>,
+    boxes: &[JunctionBox],
+    connections: impl IntoIterator<Item = Connection>,
     target_connections: usize,
-) -> Vec<Vec<usize>> {
-    let mut circuits: Vec<Vec<usize>> = Vec::new();
-    let mut num_circuits: usize = 0;
-    let mut connections_made: usize = 0;
-    let mut circuits_after_target: Vec<Vec<usize>> = Vec::new();
-    let mut num_active_circuits = 0;
-    let mut one_circuit = false;
-
-    'outer: for (idx, connection) in connections.iter().enumerate() {
+) -> CircuitReport {
+    let mut circuits = Circuits::new(boxes.len());
+    let mut made: usize = 0;
+    let mut top_three = Err(TopThreeError::NotEnoughConnections { made });
+    let mut final_connection = None;
+
+    if target_connections == 0 {
+        top_three = evaluate_circuits(&circuits.sizes());
+    }
+
+    for (idx, connection) in connections.into_iter().enumerate() {
         let b1_idx = connection.box1_idx;
         let b2_idx = connection.box2_idx;
-        println!(
-            "\nConnection #{}: Boxes {}:{:?} and {}:{:?} (dist {})",
-            idx, b1_idx, boxes[b1_idx], b2_idx, boxes[b2_idx], connection.dist
-        );
+        made = idx + 1;
 
-        match (boxes[b1_idx].circuit_num, boxes[b2_idx].circuit_num) {
-            (None, None) => {
-                // Neither box has a connection. Add a new circuit
-                circuits.push(vec![b1_idx, b2_idx]);
-                boxes[b1_idx].circuit_num = Some(num_circuits);
-                boxes[b2_idx].circuit_num = Some(num_circuits);
-                // println!(
-                //     "New circuit ID {} has IDs {:?}",
-                //     num_circuits, &circuits[num_circuits]
-                // );
-                num_circuits += 1;
-                num_active_circuits += 1;
-                connections_made += 1;
-            }
-            (None, Some(c2)) => {
-                // Add box1 to box2's circuit
-                boxes[b1_idx].circuit_num = Some(c2);
-                circuits[c2].push(b1_idx);
-                // println!(
-                //     "Adding box {} to {}'s circuit {}: {:?}",
-                //     b1_idx, b2_idx, c2, circuits[c2]
-                // );
-                connections_made += 1;
-            }
-            (Some(c1), None) => {
-                // Add box2 to box1's circuit
-                boxes[b2_idx].circuit_num = Some(c1);
-                circuits[c1].push(b2_idx);
-                // println!(
-                //     "Adding box {} to {}'s circuit {}: {:?}",
-                //     b2_idx, b1_idx, c1, circuits[c1]
-                // );
-                connections_made += 1;
-            }
-            (Some(c1), Some(c2)) => {
-                // Both boxes have an existing circuit. Merge if not already
-                if c1 != c2 {
-                    // println!("Merging circuit {} into circuit {}", c2, c1);
-                    for &id in &circuits[c2] {
-                        boxes[id].circuit_num = Some(c1);
-                    }
-                    let mut temp: Vec<usize> = Vec::with_capacity(circuits[c2].len()); // Borrow checker
-                    temp.append(&mut circuits[c2]);
-                    circuits[c1].append(&mut temp);
-                    // println!(
-                    //     "Circuits[{}] (len {}): {:?}",
-                    //     c1,
-                    //     circuits[c1].len(),
-                    //     circuits[c1]
-                    // );
-                    num_active_circuits -= 1;
-                    connections_made += 1;
-                } else {
-                    // Do nothing
-                    // println!(
-                    //     "Boxes {} and {} are already in circuit {}",
-                    //     b1_idx, b2_idx, c1
-                    // );
-                    connections_made += 1;
-                }
-            }
+        // Boxes already in the same circuit are left as they are
+        let merged = circuits.connect(b1_idx, b2_idx);
+
+        if made == target_connections {
+            top_three = evaluate_circuits(&circuits.sizes());
         }
 
-        // print!("{}: ", idx);
-        if circuits.len() >= 3 {
-            evaluate_circuits(&circuits, boxes.len());
+        // Check for the first time they make one large circuit
+        if merged && circuits.count == 1 {
+            final_connection = Some(FinalConnection {
+                connection_idx: idx,
+                box1_idx: b1_idx,
+                box2_idx: b2_idx,
+                box1: boxes[b1_idx],
+                box2: boxes[b2_idx],
+                x_product: boxes[b1_idx].x * boxes[b2_idx].x,
+            });
         }
-        // println!("Connections Made: {}", connections_made);
 
-        if connections_made == target_connections {
-            println!("End of connecting!\n\n");
-            circuits_after_target = circuits.clone();
+        if final_connection.is_some() && made >= target_connections {
+            break;
         }
+    }
 
-        // Check for the first time they make one large circuit
-        // if connections_made > target_connections && num_active_circuits == 1 {
-        //     println!("\n -------\nOne circuit remains!");
-        //     println!("Final boxes connected: {:?} and {:?}", boxes[b1_idx], boxes[b2_idx]);
-        //     println!("Product of x coords: {} * {} = {}", boxes[b1_idx].x, boxes[b2_idx].x, boxes[b1_idx].x * boxes[b2_idx].x);
-        //     println!("-------");
-        //     break;
-        // }
+    if made < target_connections {
+        top_three = Err(TopThreeError::NotEnoughConnections { made });
     }
 
-    //
-    println!("Evaluating circuits after the target:");
-    evaluate_circuits(&circuits_after_target, boxes.len());
+    CircuitReport {
+        top_three,
+        final_connection,
+    }
+}
 
-    circuits
+/// The boxes and connections after a number of connections have been made, for exporting
+#[derive(Debug)]
+struct CircuitSnapshot<'a> {
+    boxes: &'a [JunctionBox],
+    /// Connections made so far, shortest first
+    connections: Vec<Connection>,
+    /// Root box of each box's circuit
+    circuit: Vec<usize>,
+    /// Number of boxes in each box's circuit
+    size: Vec<usize>,
 }
 
-fn evaluate_circuits(circuits: &Vec<Vec<usize>>, total: usize) {
-    let count = circuits.len();
-    println!("Circuits: {:?}", circuits);
+impl<'a> CircuitSnapshot<'a> {
+    /// Makes the first count connections, the same way track_circuits does
+    fn new(
+        boxes: &'a [JunctionBox],
+        connections: impl IntoIterator<Item = Connection>,
+        count: usize,
+    ) -> Self {
+        let mut circuits = Circuits::new(boxes.len());
+        let connections: Vec<Connection> = connections.into_iter().take(count).collect();
+        for connection in &connections {
+            circuits.connect(connection.box1_idx, connection.box2_idx);
+        }
 
-    if count >= 3 {
-        // Get multiplied number of connections from the three largest circuits
-        let mut num_connections_sorted: Vec<usize> = circuits.iter().map(|c| c.len()).collect();
-        let sum: usize = num_connections_sorted.iter().sum();
-        num_connections_sorted.sort();
-        println!(
-            "Num circuit connections ({}): {:?}",
-            count, num_connections_sorted
-        );
-        println!("Sum of connections: {}", sum);
-        let mult_three = num_connections_sorted[count - 3]
-            * num_connections_sorted[count - 2]
-            * num_connections_sorted[count - 1];
+        let circuit: Vec<usize> = (0..boxes.len()).map(|idx| circuits.find(idx)).collect();
+        let size: Vec<usize> = circuit.iter().map(|&root| circuits.size[root]).collect();
+        CircuitSnapshot {
+            boxes,
+            connections,
+            circuit,
+            size,
+        }
+    }
 
-        println!(
-            "Top three circuit sizes: {} * {} * {} = {}",
-            num_connections_sorted[count - 3],
-            num_connections_sorted[count - 2],
-            num_connections_sorted[count - 1],
-            mult_three
-        );
+    /// Colour of the box's circuit, grey for boxes on their own
+    fn colour(&self, idx: usize) -> (u8, u8, u8) {
+        if self.size[idx] == 1 {
+            return (128, 128, 128);
+        }
+        // Spread the circuit numbers out so circuits next to each other look different
+        let hash = (self.circuit[idx] as u32 + 1).wrapping_mul(0x9e37_79b1);
+        let [r, g, b, _] = hash.to_be_bytes();
+        (r, g, b)
+    }
 
-        if num_connections_sorted[count - 1] == total {
-            panic!("Found last pair!");
+    /// Writes an ASCII PLY file: boxes as coloured vertices, connections as edges
+    fn write_ply(&self) -> String {
+        let mut out = String::new();
+        out.push_str("ply\nformat ascii 1.0\n");
+        out.push_str(&format!("element vertex {}\n", self.boxes.len()));
+        out.push_str("property float x\nproperty float y\nproperty float z\n");
+        out.push_str("property uchar red\nproperty uchar green\nproperty uchar blue\n");
+        out.push_str(&format!("element edge {}\n", self.connections.len()));
+        out.push_str("property int vertex1\nproperty int vertex2\n");
+        out.push_str("end_header\n");
+
+        for (idx, jbox) in self.boxes.iter().enumerate() {
+            let (r, g, b) = self.colour(idx);
+            out.push_str(&format!(
+                "{} {} {} {} {} {}\n",
+                jbox.x, jbox.y, jbox.z, r, g, b
+            ));
         }
+        for connection in &self.connections {
+            out.push_str(&format!(
+                "{} {}\n",
+                connection.box1_idx, connection.box2_idx
+            ));
+        }
+
+        out
+    }
+
+    /// Writes a Graphviz graph: boxes as nodes filled with their circuit's colour,
+    /// connections as edges labelled with their length
+    fn write_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("graph circuits {\n");
+        out.push_str("  node [style=filled];\n");
+
+        for (idx, jbox) in self.boxes.iter().enumerate() {
+            let (r, g, b) = self.colour(idx);
+            out.push_str(&format!(
+                "  b{} [label=\"{},{},{}\", fillcolor=\"#{:02x}{:02x}{:02x}\"];\n",
+                idx, jbox.x, jbox.y, jbox.z, r, g, b
+            ));
+        }
+        for connection in &self.connections {
+            out.push_str(&format!(
+                "  b{} -- b{} [label=\"{:.1}\"];\n",
+                connection.box1_idx,
+                connection.box2_idx,
+                (connection.dist as f64).sqrt()
+            ));
+        }
+
+        out.push_str("}\n");
+        out
     }
 }
 
+/// One step of single-linkage clustering: the two closest clusters joining together
+/// Clusters are numbered like a linkage matrix: box i starts as cluster i, and the
+/// cluster made by merge m is numbered boxes + m
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Merge {
+    connection: Connection,
+    cluster1: usize,
+    cluster2: usize,
+    /// Number of boxes in the joined cluster
+    size: usize,
+}
+
+/// Minimum spanning tree of the boxes, kept as the order its connections join clusters
+/// The same connections answer every single-linkage clustering question, as clusters at
+/// any distance are the pieces left when only tree connections up to that distance are used
+#[derive(Debug)]
+struct SpanningTree {
+    boxes: usize,
+    /// Tree connections, shortest first
+    merges: Vec<Merge>,
+}
+
+/// Builds the spanning tree by taking connections shortest first and keeping the ones that
+/// join two different circuits (Kruskal's algorithm)
+/// Stops once everything is joined, so the connections can be an endless or lazy stream
+fn spanning_tree(boxes: usize, connections: impl IntoIterator<Item = Connection>) -> SpanningTree {
+    let mut circuits = Circuits::new(boxes);
+    // Cluster number currently held by each circuit's root box
+    let mut cluster_of: Vec<usize> = (0..boxes).collect();
+    let mut merges: Vec<Merge> = Vec::with_capacity(boxes.saturating_sub(1));
+
+    for connection in connections {
+        if circuits.count <= 1 {
+            break;
+        }
+        let root1 = circuits.find(connection.box1_idx);
+        let root2 = circuits.find(connection.box2_idx);
+        if !circuits.connect(root1, root2) {
+            continue;
+        }
+
+        let root = circuits.find(root1);
+        merges.push(Merge {
+            connection,
+            cluster1: cluster_of[root1],
+            cluster2: cluster_of[root2],
+            size: circuits.size[root],
+        });
+        cluster_of[root] = boxes + merges.len() - 1;
+    }
+
+    SpanningTree { boxes, merges }
+}
+
+impl SpanningTree {
+    /// Sum of the actual (not squared) lengths of the tree connections
+    fn total_length(&self) -> f64 {
+        self.merges
+            .iter()
+            .map(|merge| (merge.connection.dist as f64).sqrt())
+            .sum()
+    }
+
+    /// Number of clusters when every pair of boxes within the threshold (squared distance)
+    /// is connected
+    fn clusters_within(&self, threshold: u64) -> usize {
+        let joined = self
+            .merges
+            .partition_point(|merge| merge.connection.dist <= threshold);
+        self.boxes - joined
+    }
+
+    /// Smallest threshold (squared distance) that leaves at most k clusters
+    /// None if k is 0, or the connections ran out before getting down to k clusters
+    fn threshold_for(&self, k: usize) -> Option<u64> {
+        if k == 0 {
+            return None;
+        }
+        if k >= self.boxes {
+            return Some(0);
+        }
+        self.merges
+            .get(self.boxes - k - 1)
+            .map(|merge| merge.connection.dist)
+    }
+
+    /// Writes the dendrogram as a linkage matrix, one merge per line:
+    /// `cluster1 cluster2 distance size`
+    fn write_dendrogram(&self) -> String {
+        self.merges
+            .iter()
+            .map(|merge| {
+                format!(
+                    "{} {} {:.6} {}",
+                    merge.cluster1,
+                    merge.cluster2,
+                    (merge.connection.dist as f64).sqrt(),
+                    merge.size
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Returns the product of the three largest circuit sizes, if there are three circuits
+fn evaluate_circuits(sizes: &[usize]) -> Result<usize, TopThreeError> {
+    let count = sizes.len();
+
+    // Get multiplied number of connections from the three largest circuits
+    let mut sizes_sorted: Vec<usize> = sizes.to_vec();
+    sizes_sorted.sort();
+
+    if count < 3 {
+        return Err(TopThreeError::FewerThanThreeCircuits(count));
+    }
+
+    let mult_three = sizes_sorted[count - 3] * sizes_sorted[count - 2] * sizes_sorted[count - 1];
+
+    Ok(mult_three)
+}
+
 //fn get_idx_by_id(boxes: &Vec<JunctionBox>, id: usize) -> Option<usize> {
 //    boxes.iter().position(|b| b.id == id)
 //}
 
-/// Boxes should already be sorted by connection distance
-fn form_connections<'a>(boxes: &'a Vec<JunctionBox>) -> Vec<Connection> {
+/// Every connection between two boxes, sorted by distance
+/// Needs all n^2 / 2 connections at once, so NearestPairs is used instead
+fn _form_connections(boxes: &[JunctionBox]) -> Vec<Connection> {
     let count = boxes.len();
     let mut connections: Vec<Connection> = Vec::with_capacity(count * count / 2);
     for i in 0..count {
@@ -220,6 +523,177 @@ fn form_connections<'a>(boxes: &'a Vec<JunctionBox>) -> Vec<Connection> {
     connections
 }
 
+/// k-d tree over the boxes, for finding the boxes closest to a box
+/// Stored flat: each range of the list has its splitting box in the middle, with the boxes
+/// on the low side of it (along that level's axis) before it and the high side after it
+///
+/// ```text
+///   [ low x ... | split on x | high x ... ]
+///   [ low y | split on y | high y ]
+/// ```
+#[derive(Debug)]
+struct KdTree<'a> {
+    boxes: &'a [JunctionBox],
+    order: Vec<usize>,
+}
+
+impl<'a> KdTree<'a> {
+    fn new(boxes: &'a [JunctionBox]) -> Self {
+        let mut order: Vec<usize> = (0..boxes.len()).collect();
+        Self::build(boxes, &mut order, 0);
+        KdTree { boxes, order }
+    }
+
+    /// Puts the median box along the axis in the middle, then does the same for each side
+    fn build(boxes: &[JunctionBox], order: &mut [usize], depth: usize) {
+        if order.len() <= 1 {
+            return;
+        }
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by_key(mid, |&idx| (axis(&boxes[idx], depth), idx));
+        let (low, high) = order.split_at_mut(mid);
+        Self::build(boxes, low, depth + 1);
+        Self::build(boxes, &mut high[1..], depth + 1);
+    }
+
+    /// Returns the k boxes closest to the given box (not counting itself), closest first
+    /// Ties in distance go to the lower index, so asking for more never reorders the first ones
+    fn nearest(&self, idx: usize, k: usize) -> Vec<(u64, usize)> {
+        let mut found: BinaryHeap<(u64, usize)> = BinaryHeap::with_capacity(k + 1);
+        if k > 0 {
+            self.search(idx, k, 0, self.order.len(), 0, &mut found);
+        }
+        found.into_sorted_vec()
+    }
+
+    fn search(
+        &self,
+        idx: usize,
+        k: usize,
+        start: usize,
+        end: usize,
+        depth: usize,
+        found: &mut BinaryHeap<(u64, usize)>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let split = self.order[mid];
+        let target = &self.boxes[idx];
+
+        if split != idx {
+            let candidate = (
+                target.get_distance_squared_between(&self.boxes[split]),
+                split,
+            );
+            if found.len() < k {
+                found.push(candidate);
+            } else if found.peek().is_some_and(|&worst| candidate < worst) {
+                found.pop();
+                found.push(candidate);
+            }
+        }
+
+        // Search the side the box is on first, then the other side if it could hold anything closer
+        let diff = axis(target, depth) - axis(&self.boxes[split], depth);
+        let (near, far) = if diff < 0 {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.search(idx, k, near.0, near.1, depth + 1, found);
+        let plane = diff.unsigned_abs().pow(2);
+        if found.len() < k || found.peek().is_some_and(|&(worst, _)| plane <= worst) {
+            self.search(idx, k, far.0, far.1, depth + 1, found);
+        }
+    }
+}
+
+/// Coordinate of the box along the axis the tree splits on at this depth
+fn axis(jbox: &JunctionBox, depth: usize) -> i64 {
+    match depth % 3 {
+        0 => jbox.x,
+        1 => jbox.y,
+        _ => jbox.z,
+    }
+}
+
+/// The boxes closest to one box, fetched from the tree a batch at a time
+#[derive(Debug)]
+struct Neighbours {
+    batch: Vec<(u64, usize)>,
+    next: usize,
+}
+
+/// Every connection between two boxes, shortest first (then by box indices), worked out lazily
+/// Each box has a list of its closest boxes, and a queue holds the next unused connection from
+/// each list. Taking the shortest from the queue and refilling from that box's list gives the
+/// connections in order without ever making all of them
+#[derive(Debug)]
+struct NearestPairs<'a> {
+    tree: KdTree<'a>,
+    neighbours: Vec<Neighbours>,
+    queue: BinaryHeap<Reverse<(u64, usize, usize)>>,
+}
+
+impl<'a> NearestPairs<'a> {
+    /// Number of closest boxes to find for each box to begin with
+    const FIRST_BATCH: usize = 4;
+
+    fn new(boxes: &'a [JunctionBox]) -> Self {
+        let mut pairs = NearestPairs {
+            tree: KdTree::new(boxes),
+            neighbours: (0..boxes.len())
+                .map(|_| Neighbours {
+                    batch: Vec::new(),
+                    next: 0,
+                })
+                .collect(),
+            queue: BinaryHeap::with_capacity(boxes.len()),
+        };
+        for idx in 0..boxes.len() {
+            pairs.queue_next(idx);
+        }
+        pairs
+    }
+
+    /// Queues the box's next closest box, fetching a bigger batch from the tree if it runs out
+    fn queue_next(&mut self, idx: usize) {
+        let neighbours = &mut self.neighbours[idx];
+        if neighbours.next == neighbours.batch.len() {
+            let have = neighbours.batch.len();
+            if have + 1 >= self.tree.boxes.len() {
+                return;
+            }
+            let k = (have * 2).max(Self::FIRST_BATCH);
+            neighbours.batch = self.tree.nearest(idx, k);
+        }
+        let (dist, other) = neighbours.batch[neighbours.next];
+        neighbours.next += 1;
+        self.queue.push(Reverse((dist, idx, other)));
+    }
+}
+
+impl Iterator for NearestPairs<'_> {
+    type Item = Connection;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Every connection shows up in both boxes' lists, only use it from the lower box
+        while let Some(Reverse((dist, idx, other))) = self.queue.pop() {
+            self.queue_next(idx);
+            if idx < other {
+                return Some(Connection {
+                    dist,
+                    box1_idx: idx,
+                    box2_idx: other,
+                });
+            }
+        }
+        None
+    }
+}
+
 /*
 /// Go through the list of boxes, finding the shortest connection to each one
 /// Returns the number of ciruits
@@ -271,7 +745,6 @@ fn parse_input(input: &str) -> Vec<JunctionBox> {
             // shortest_id: idx,
             // dist: u64::MAX,
             // connected_to: None,
-            circuit_num: None,
         });
         idx += 1;
     }
@@ -290,3 +763,216 @@ fn print_connections(connections: &Vec<Connection>) {
         println!("{:?}", connection);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_circuits() {
+        let mut circuits = Circuits::new(6);
+        assert_eq!(circuits.count, 6);
+
+        assert!(circuits.connect(0, 3));
+        assert!(circuits.connect(5, 0));
+        assert!(circuits.connect(1, 4));
+        assert!(!circuits.connect(3, 5));
+        assert_eq!(circuits.count, 3);
+        assert_eq!(circuits.find(5), circuits.find(3));
+        assert_ne!(circuits.find(1), circuits.find(0));
+
+        let mut sizes = circuits.sizes();
+        sizes.sort();
+        assert_eq!(sizes, vec![1, 2, 3]);
+
+        assert!(circuits.connect(4, 3));
+        assert!(circuits.connect(2, 1));
+        assert_eq!(circuits.count, 1);
+        assert_eq!(circuits.sizes(), vec![6]);
+    }
+
+    #[test]
+    fn test_test_input() {
+        let boxes = parse_input(include_str!("day8-test.txt"));
+        let connections = _form_connections(&boxes);
+
+        let mut circuits = Circuits::new(boxes.len());
+        for connection in &connections[..10] {
+            circuits.connect(connection.box1_idx, connection.box2_idx);
+        }
+        assert_eq!(circuits.count, 11);
+        let mut sizes = circuits.sizes();
+        sizes.sort();
+        assert_eq!(sizes.iter().rev().take(3).product::<usize>(), 40);
+        assert_eq!(sizes.iter().sum::<usize>(), boxes.len());
+
+        let report = track_circuits(&boxes, NearestPairs::new(&boxes), 10);
+        assert_eq!(report.top_three, Ok(40));
+        let last = report.final_connection.unwrap();
+        assert_eq!((last.box1.x, last.box1.y, last.box1.z), (216, 146, 977));
+        assert_eq!((last.box2.x, last.box2.y, last.box2.z), (117, 168, 530));
+        assert_eq!(last.x_product, 25272);
+        assert_eq!(boxes[last.box1_idx], last.box1);
+        assert_eq!(boxes[last.box2_idx], last.box2);
+
+        // Running out of connections is not an error
+        let report = track_circuits(&boxes, connections[..10].iter().copied(), 10);
+        assert_eq!(report.top_three, Ok(40));
+        assert_eq!(report.final_connection, None);
+
+        // Asking for the top three after every box is joined up
+        let report = track_circuits(&boxes, connections.iter().copied(), connections.len());
+        assert_eq!(
+            report.top_three,
+            Err(TopThreeError::FewerThanThreeCircuits(1))
+        );
+        assert_eq!(report.final_connection, Some(last));
+
+        // Asking for more connections than there are
+        let report = track_circuits(&boxes, connections[..5].iter().copied(), 10);
+        assert_eq!(
+            report.top_three,
+            Err(TopThreeError::NotEnoughConnections { made: 5 })
+        );
+    }
+
+    #[test]
+    fn test_nearest_pairs_match_all_pairs() {
+        // The real input, plus boxes on a small lattice where lots of distances tie
+        let lattice: Vec<JunctionBox> = (0..150)
+            .map(|i: i64| JunctionBox {
+                x: i % 5,
+                y: (i / 5) % 5,
+                z: (i * 7) % 6,
+            })
+            .collect();
+        for boxes in [parse_input(include_str!("day8-input.txt")), lattice] {
+            let all = _form_connections(&boxes);
+            let lazy: Vec<Connection> = NearestPairs::new(&boxes).take(30_000).collect();
+            assert_eq!(lazy, all[..lazy.len()]);
+        }
+
+        let boxes = parse_input(include_str!("day8-test.txt"));
+        let lazy: Vec<Connection> = NearestPairs::new(&boxes).collect();
+        assert_eq!(lazy, _form_connections(&boxes));
+    }
+
+    #[test]
+    fn test_kd_tree_nearest() {
+        let boxes = parse_input(include_str!("day8-test.txt"));
+        let tree = KdTree::new(&boxes);
+        for idx in 0..boxes.len() {
+            let mut expected: Vec<(u64, usize)> = (0..boxes.len())
+                .filter(|&other| other != idx)
+                .map(|other| {
+                    (
+                        boxes[idx].get_distance_squared_between(&boxes[other]),
+                        other,
+                    )
+                })
+                .collect();
+            expected.sort();
+            for k in [0, 1, 5, boxes.len()] {
+                assert_eq!(tree.nearest(idx, k), expected[..k.min(expected.len())]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_spanning_tree() {
+        let boxes = parse_input(include_str!("day8-test.txt"));
+        let connections = _form_connections(&boxes);
+        let tree = spanning_tree(boxes.len(), NearestPairs::new(&boxes));
+        assert_eq!(tree.merges.len(), boxes.len() - 1);
+        assert_eq!(tree.merges.last().unwrap().size, boxes.len());
+
+        // The last tree connection is the one that joins every box into one circuit
+        let last = track_circuits(&boxes, connections.iter().copied(), 10)
+            .final_connection
+            .unwrap();
+        assert_eq!(
+            tree.merges.last().unwrap().connection,
+            connections[last.connection_idx]
+        );
+        assert_eq!(
+            tree.threshold_for(1),
+            Some(connections[last.connection_idx].dist)
+        );
+
+        // After the first 10 connections there are 11 circuits
+        assert_eq!(tree.clusters_within(connections[9].dist), 11);
+        assert_eq!(tree.clusters_within(0), boxes.len());
+        assert_eq!(tree.threshold_for(0), None);
+        assert_eq!(tree.threshold_for(boxes.len()), Some(0));
+
+        // The first connection joins two lone boxes into cluster 20, the second grows it
+        let dendrogram = tree.write_dendrogram();
+        let lines: Vec<&str> = dendrogram.lines().collect();
+        assert_eq!(lines.len(), boxes.len() - 1);
+        assert!(lines[0].starts_with("0 19 316.902"));
+        assert!(lines[0].ends_with(" 2"));
+        assert_eq!(lines[1], "20 7 321.560259 3");
+    }
+
+    #[test]
+    fn test_clustering_thresholds() {
+        let boxes = parse_input(include_str!("day8-input.txt"));
+        let connections = _form_connections(&boxes);
+        let tree = spanning_tree(boxes.len(), NearestPairs::new(&boxes));
+        assert_eq!(tree.merges.len(), boxes.len() - 1);
+
+        // Joining every pair within the threshold one by one gives the same number of clusters
+        let mut circuits = Circuits::new(boxes.len());
+        let mut used = 0;
+        for threshold in [0, 100_000, 10_000_000, 50_000_000, 200_000_000] {
+            while used < connections.len() && connections[used].dist <= threshold {
+                circuits.connect(connections[used].box1_idx, connections[used].box2_idx);
+                used += 1;
+            }
+            assert_eq!(tree.clusters_within(threshold), circuits.count);
+        }
+
+        for k in [1, 2, 3, 10, 500, 999] {
+            let threshold = tree.threshold_for(k).unwrap();
+            assert!(tree.clusters_within(threshold) <= k);
+            assert!(tree.clusters_within(threshold - 1) > k);
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let boxes = parse_input(include_str!("day8-test.txt"));
+        let snapshot = CircuitSnapshot::new(&boxes, NearestPairs::new(&boxes), 10);
+        assert_eq!(snapshot.connections.len(), 10);
+
+        let ply = snapshot.write_ply();
+        let (header, body) = ply.split_once("end_header\n").unwrap();
+        assert!(header.contains("element vertex 20\n"));
+        assert!(header.contains("element edge 10\n"));
+        let lines: Vec<&str> = body.lines().collect();
+        assert_eq!(lines.len(), 30);
+        assert_eq!(
+            lines[0].split(' ').take(3).collect::<Vec<_>>(),
+            ["162", "817", "812"]
+        );
+        assert_eq!(lines[20], "0 19");
+
+        // Boxes in the same circuit share a colour, lone boxes are grey
+        let colour = |idx: usize| lines[idx].split(' ').skip(3).collect::<Vec<_>>();
+        assert_eq!(colour(0), colour(19));
+        assert_ne!(colour(0), colour(2));
+        let lone = (0..20)
+            .filter(|&idx| colour(idx) == ["128", "128", "128"])
+            .count();
+        assert_eq!(lone, 7);
+
+        let dot = snapshot.write_dot();
+        assert!(dot.starts_with("graph circuits {\n"));
+        assert_eq!(dot.matches(" -- ").count(), 10);
+        assert!(dot.contains("  b0 -- b19 [label=\"316.9\"];\n"));
+        assert!(dot.contains("  b0 [label=\"162,817,812\", fillcolor=\"#"));
+
+        let empty = CircuitSnapshot::new(&boxes, NearestPairs::new(&boxes), 0);
+        assert!(empty.write_ply().contains("element edge 0\n"));
+    }
+}
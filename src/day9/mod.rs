@@ -1,4 +1,7 @@
+use crate::compress::CompressedGrid;
+use crate::settings::{output_path, setting, write_output};
 use std::fmt::Display;
+use std::str::FromStr;
 use std::vec;
 
 pub(crate) fn solve_day9() {
@@ -7,46 +10,113 @@ pub(crate) fn solve_day9() {
     // let input = include_str!("day9-test.txt");
 
     let mut points: Vec<Point> = parse_input(input);
+    // The first loop of red tiles is the outside, any others are holes
+    let loops = parse_loops(input);
 
     // let max_area = largest_area(&points);
     let areas = get_areas(&points);
     let max_area = areas[0].area;
 
-    println!("The largest are rectangle you can make is {}", max_area); // 4773451098
+    println!(
+        "The largest are rectangle you can make is {}: {:?} to {:?}",
+        max_area, points[areas[0].p1_idx], points[areas[0].p2_idx]
+    ); // 4773451098
 
-    let (x_map, y_map) = condense_point_space(&mut points);
-    let mut lines: Vec<Line> = form_lines(&points);
+    let grid = condense_point_space(&mut points);
+    let mut lines: Vec<Line> = split_loops(&points, &loops)
+        .into_iter()
+        .flat_map(form_lines)
+        .collect();
 
-    // Tests
-    // test_points(&points, &x_map, &y_map);
-    // test_map_monotonic(&x_map);
-    // test_map_monotonic(&y_map);
+    let mut squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
 
-    let mut squares = populate_map(&mut lines, x_map.len(), y_map.len());
+    // Cross-check the map against counting edge crossings
+    let classifier = PolygonClassifier::new(&loops);
+    for rule in [FillRule::EvenOdd, FillRule::NonZero] {
+        let classified = classifier.classify_map(&grid, rule);
+        let mismatches = count_mismatches(&squares, &classified);
+        println!(
+            "The {:?} edge crossing classifier disagrees on {} squares",
+            rule, mismatches
+        );
+    }
+    // Filling in the map can't tell a hole from the inside, so the classifier takes over
+    if loops.len() > 1 {
+        squares = classifier.classify_map(&grid, FillRule::EvenOdd);
+    }
 
-    println!("\nx_map (len {}): {:?}", x_map.len(), x_map);
-    println!("\ny_map (len {}): {:?}", y_map.len(), y_map);
+    println!(
+        "\nMap: {} x {} squares, covering {} tiles inside the polygon",
+        grid.x.len(),
+        grid.y.len(),
+        covered_area(&squares, &grid)
+    );
 
     // println!("\nPoints: {:?}", points);
     // println!("\nLines: {:?}", lines);
 
-    let (idx, max_constrained_area) =
-        largest_constrained_area(&areas, &points, &mut squares, &lines);
-
-    // Draw the rectangle
-    println!("Showing area[{}]", idx);
-    draw_rectangle(
-        &points[areas[idx].p1_idx],
-        &points[areas[idx].p2_idx],
-        &mut squares,
-    );
+    // Checking rectangles against the polygon's edges works without the map
+    // DAY9_CHECK picks which of them finds the rectangle, map or edges
+    let edges = PolygonEdges::new(&split_loops(&points, &loops));
+    let check = setting("DAY9_CHECK", RectangleCheck::Map);
 
-    print_squares(&squares);
+    // let (idx, max_constrained_area) =
+    //     _largest_constrained_area(&areas, &points, &squares, &edges, check);
     // Checking area[103606]: 1429043625 is too low
     // Checking area[102000]: 1462492632 is too high
     // Checking area[94761]: 1621520882 is too high
 
-    println!("Largest constrained area: {}", max_constrained_area);
+    let Some(rectangle) = largest_inscribed_rectangle(&points, &grid, &squares, &edges, check)
+    else {
+        println!("No rectangle fits inside the polygon");
+        return;
+    };
+
+    // The square by square and edge checks should agree
+    for check in [RectangleCheck::Map, RectangleCheck::Edges] {
+        if !is_rectangle_within_polygon(
+            &rectangle.corner1,
+            &rectangle.corner2,
+            &squares,
+            &edges,
+            check,
+        ) {
+            println!(
+                "The {:?} check says the rectangle is not inside the polygon",
+                check
+            );
+        }
+    }
+
+    // Render the polygon with the chosen rectangle and the largest rectangles that were candidates
+    let candidates = top_candidates(&areas, &points, 10);
+    if let Some(path) = output_path("day9.svg") {
+        let svg = render_svg(
+            &split_loops(&points, &loops),
+            &squares,
+            &grid,
+            Some(&rectangle),
+            &candidates,
+        );
+        write_output(path, svg);
+    }
+    if let Some(path) = output_path("day9.png") {
+        let png = render_png(&squares, 4, Some(&rectangle), &candidates);
+        write_output(path, png);
+    }
+
+    // Draw the rectangle
+    draw_rectangle(&rectangle.corner1, &rectangle.corner2, &mut squares);
+    print_squares(&squares);
+
+    println!(
+        "Largest constrained area: ({}, {}) to ({}, {}) = {}",
+        rectangle.corner1.x,
+        rectangle.corner1.y,
+        rectangle.corner2.x,
+        rectangle.corner2.y,
+        rectangle.area
+    );
     // 1429075575 is just right!
 }
 
@@ -153,11 +223,12 @@ impl<'a> Line<'a> {
     }
 }
 
-fn largest_constrained_area(
+fn _largest_constrained_area(
     areas: &Vec<Area>,
     points: &Vec<Point>,
-    squares: &mut Vec<Vec<Square>>,
-    lines: &Vec<Line>,
+    squares: &[Vec<Square>],
+    edges: &PolygonEdges,
+    check: RectangleCheck,
 ) -> (usize, u64) {
     let mut max_area = 0;
     let mut p1: usize = 0;
@@ -166,8 +237,13 @@ fn largest_constrained_area(
 
     for (i, area) in areas.iter().enumerate() {
         // println!("Checking area[{}]: {}", i, area.area);
-        if is_rectangle_within_polygon(&points[area.p1_idx], &points[area.p2_idx], &squares, lines)
-        {
+        if is_rectangle_within_polygon(
+            &points[area.p1_idx],
+            &points[area.p2_idx],
+            squares,
+            edges,
+            check,
+        ) {
             p1 = area.p1_idx;
             p2 = area.p2_idx;
             max_area = area.area;
@@ -202,26 +278,263 @@ fn largest_constrained_area(
     (idx, max_area)
 }
 
+/// Ways of checking whether a rectangle fits inside the polygon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RectangleCheck {
+    /// Look for outside squares on the condensed map
+    Map,
+    /// Look for outside tiles next to the polygon's edges, in the original coordinates
+    Edges,
+}
+
+impl FromStr for RectangleCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "map" => Ok(Self::Map),
+            "edges" => Ok(Self::Edges),
+            _ => Err(format!("expected map or edges, not {}", s)),
+        }
+    }
+}
+
 fn is_rectangle_within_polygon(
     p1: &Point,
     p2: &Point,
-    squares: &Vec<Vec<Square>>,
-    lines: &Vec<Line>,
+    squares: &[Vec<Square>],
+    edges: &PolygonEdges,
+    check: RectangleCheck,
 ) -> bool {
-    // Check using the map
-    let mut map_check = true;
-    for x in p1.map_x.min(p2.map_x)..=p1.map_x.max(p2.map_x) {
-        for y in p1.map_y.min(p2.map_y)..=p1.map_y.max(p2.map_y) {
-            if squares[y][x] == Square::Outside {
-                map_check = false;
-                break;
+    match check {
+        RectangleCheck::Map => {
+            // Check using the map
+            let xs = p1.map_x.min(p2.map_x)..=p1.map_x.max(p2.map_x);
+            let ys = p1.map_y.min(p2.map_y)..=p1.map_y.max(p2.map_y);
+            squares[ys].iter().all(|row| {
+                row[xs.clone()]
+                    .iter()
+                    .all(|&square| square != Square::Outside)
+            })
+        }
+        RectangleCheck::Edges => edges.contains_rectangle(p1, p2),
+    }
+}
+
+/// Edge of the polygon between two red tiles, in the original coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Edge {
+    start: (i64, i64),
+    end: (i64, i64),
+    /// One step away from the edge on its outside
+    outward: (i64, i64),
+}
+
+/// The polygon's edges, for checking rectangles without the condensed map
+///
+/// If a rectangle has a tile outside the polygon, walking from it towards a red corner must
+/// step off the outside straight onto an edge. That step goes the opposite way to the edge's
+/// outward side (or off the end of an edge at a corner that points outwards), so the
+/// rectangle only needs checking along the row of tiles just outside each edge:
+///
+/// ```text
+///   #-------#      . = tiles just outside the bottom edge, checked against the rectangle
+///   |       |
+///   #---#   |      A tile there is outside unless another edge runs through it, which
+///   ....|   |      happens when two edges are a single tile apart
+///       #---#
+/// ```
+#[derive(Debug)]
+struct PolygonEdges {
+    edges: Vec<Edge>,
+    classifier: PolygonClassifier,
+}
+
+impl PolygonEdges {
+    /// The first loop is the outside, any others are holes going the other way round
+    fn new(loops: &[&[Point]]) -> Self {
+        let corner = |point: &Point| (point.x as i64, point.y as i64);
+        // Each red tile and the next one round its loop
+        let sides = || {
+            loops.iter().flat_map(|points| {
+                (0..points.len())
+                    .map(|i| (corner(&points[i]), corner(&points[(i + 1) % points.len()])))
+            })
+        };
+
+        // Work out which way round the outside goes from the signed area,
+        // holes going the other way only take away from it
+        let twice_area: i128 = sides()
+            .map(|((x1, y1), (x2, y2))| x1 as i128 * y2 as i128 - x2 as i128 * y1 as i128)
+            .sum();
+        let turn = if twice_area > 0 { 1 } else { -1 };
+
+        let edges: Vec<Edge> = sides()
+            .filter_map(|(start, end)| {
+                let step = ((end.0 - start.0).signum(), (end.1 - start.1).signum());
+                // Repeated red tiles make no edge
+                (step != (0, 0)).then_some(Edge {
+                    start,
+                    end,
+                    outward: (turn * step.1, -turn * step.0),
+                })
+            })
+            .collect();
+
+        let loops: Vec<Vec<Point>> = loops.iter().map(|points| points.to_vec()).collect();
+        PolygonEdges {
+            edges,
+            classifier: PolygonClassifier::new(&loops),
+        }
+    }
+
+    /// Whether every tile in the rectangle between the two corners is red, green or inside
+    fn contains_rectangle(&self, p1: &Point, p2: &Point) -> bool {
+        let (left, right) = (p1.x.min(p2.x) as i64, p1.x.max(p2.x) as i64);
+        let (top, bottom) = (p1.y.min(p2.y) as i64, p1.y.max(p2.y) as i64);
+        let mut touched = false;
+
+        for edge in &self.edges {
+            let (x_lo, x_hi) = (edge.start.0.min(edge.end.0), edge.start.0.max(edge.end.0));
+            let (y_lo, y_hi) = (edge.start.1.min(edge.end.1), edge.start.1.max(edge.end.1));
+            // Part of the edge inside the rectangle, touching its sides counts
+            let (x_lo, x_hi) = (x_lo.max(left), x_hi.min(right));
+            let (y_lo, y_hi) = (y_lo.max(top), y_hi.min(bottom));
+            if x_lo > x_hi || y_lo > y_hi {
+                continue;
+            }
+            touched = true;
+
+            // The row of tiles just outside that part of the edge, if it is in the rectangle
+            let (x_lo, x_hi) = (x_lo + edge.outward.0, x_hi + edge.outward.0);
+            let (y_lo, y_hi) = (y_lo + edge.outward.1, y_hi + edge.outward.1);
+            if x_lo < left || x_hi > right || y_lo < top || y_hi > bottom {
+                continue;
+            }
+            if !self.covers((x_lo, y_lo), (x_hi, y_hi)) {
+                return false;
+            }
+        }
+
+        // Without any edges in the way, the whole rectangle is on one side
+        touched
+            || self
+                .classifier
+                .classify(left as u64, top as u64, FillRule::EvenOdd)
+                != Square::Outside
+    }
+
+    /// Whether every tile on the straight run between the two tiles lies on an edge
+    fn covers(&self, start: (i64, i64), end: (i64, i64)) -> bool {
+        // Pieces of the run on each edge, along the run
+        let along = |point: (i64, i64)| if start.1 == end.1 { point.0 } else { point.1 };
+        let (run_lo, run_hi) = (along(start), along(end));
+        let mut pieces: Vec<(i64, i64)> = Vec::new();
+        for edge in &self.edges {
+            let (x_lo, x_hi) = (edge.start.0.min(edge.end.0), edge.start.0.max(edge.end.0));
+            let (y_lo, y_hi) = (edge.start.1.min(edge.end.1), edge.start.1.max(edge.end.1));
+            let (x_lo, x_hi) = (x_lo.max(start.0), x_hi.min(end.0));
+            let (y_lo, y_hi) = (y_lo.max(start.1), y_hi.min(end.1));
+            if x_lo <= x_hi && y_lo <= y_hi {
+                pieces.push((along((x_lo, y_lo)), along((x_hi, y_hi))));
             }
         }
+        pieces.sort();
+
+        // Look for a gap between the pieces
+        let mut next = run_lo;
+        for (lo, hi) in pieces {
+            if lo > next {
+                return false;
+            }
+            next = next.max(hi + 1);
+        }
+        next > run_hi
+    }
+}
+
+/// Running count of outside squares on the map, so any rectangle can be checked at once
+///
+/// sums[y][x] is the number of outside squares above and to the left of (x, y), so the count
+/// in a rectangle is the corner sums added and taken away:
+///
+/// ```text
+///   A-----B
+///   |     |    count = D - B - C + A
+///   C-----D
+/// ```
+#[derive(Debug)]
+struct OutsideCounts {
+    sums: Vec<Vec<u32>>,
+}
+
+impl OutsideCounts {
+    fn new(squares: &[Vec<Square>]) -> Self {
+        let width = squares.first().map_or(0, |row| row.len());
+        let mut sums: Vec<Vec<u32>> = vec![vec![0; width + 1]; squares.len() + 1];
+        for (y, row) in squares.iter().enumerate() {
+            for (x, &square) in row.iter().enumerate() {
+                sums[y + 1][x + 1] = sums[y][x + 1] + sums[y + 1][x] - sums[y][x]
+                    + u32::from(square == Square::Outside);
+            }
+        }
+        OutsideCounts { sums }
+    }
+
+    /// Number of outside squares in the rectangle between the two map coordinates (inclusive)
+    fn count(&self, (x1, y1): (usize, usize), (x2, y2): (usize, usize)) -> u32 {
+        let (left, right) = (x1.min(x2), x1.max(x2) + 1);
+        let (top, bottom) = (y1.min(y2), y1.max(y2) + 1);
+        self.sums[bottom][right] + self.sums[top][left]
+            - self.sums[top][right]
+            - self.sums[bottom][left]
     }
+}
+
+/// A rectangle with red tiles on opposite corners
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rectangle {
+    corner1: Point,
+    corner2: Point,
+    /// Number of tiles covered in the original coordinates
+    area: u64,
+}
 
-    // let line_check = _line_check_rectangle_within_polygon(p1, p2, squares, lines, map_check);
+/// Finds the largest rectangle with red corners that is fully inside the polygon
+/// Areas come from the tiles the rectangle's map squares cover
+/// Every pair of red tiles is checked against the running counts of outside squares, so there
+/// is no need to sort the areas or scan each rectangle square by square
+fn largest_inscribed_rectangle(
+    points: &[Point],
+    grid: &CompressedGrid,
+    squares: &[Vec<Square>],
+    edges: &PolygonEdges,
+    check: RectangleCheck,
+) -> Option<Rectangle> {
+    let outside = OutsideCounts::new(squares);
+    let fits = |p1: &Point, p2: &Point| match check {
+        RectangleCheck::Map => outside.count((p1.map_x, p1.map_y), (p2.map_x, p2.map_y)) == 0,
+        RectangleCheck::Edges => edges.contains_rectangle(p1, p2),
+    };
+    let mut best: Option<Rectangle> = None;
 
-    return map_check;
+    for (l, p1) in points.iter().enumerate() {
+        for p2 in &points[..l] {
+            let area = grid.area_between((p1.map_x, p1.map_y), (p2.map_x, p2.map_y));
+            if best.is_some_and(|best| best.area >= area) {
+                continue;
+            }
+            if fits(p1, p2) {
+                best = Some(Rectangle {
+                    corner1: *p1,
+                    corner2: *p2,
+                    area,
+                });
+            }
+        }
+    }
+
+    best
 }
 
 fn draw_rectangle(
@@ -327,7 +640,7 @@ fn populate_point_interior(
         // Short circuit if any point fails the check
 
         // Note: for a shape with holes, extend to the end and count the number of intersections
-        // and a point is inside if it has an odd number in each direction
+        // and a point is inside if it has an odd number in each direction (see PolygonClassifier)
 
         let mut inside = false;
 
@@ -436,58 +749,171 @@ fn populate_point_interior(
     }
 }
 
-/// Maps the points to a condensed space of coordinates corresponding to an index of the map
-///
-/// Ex:
-///  012345678 ->  012
-/// 0....#....    0.#.
-/// 1......#..    1..#
-/// 2.........    2#..
-/// 3.#.......
-///
-fn condense_point_space(points: &mut Vec<Point>) -> (Vec<u64>, Vec<u64>) {
-    let count = points.len();
-    let mut x_map: Vec<u64> = Vec::with_capacity(count / 2 + count / 8);
-    let mut y_map: Vec<u64> = Vec::with_capacity(count / 2 + count / 8);
-
-    let mut points_sorted: Vec<Point> = points.clone();
-
-    // Map X coordinates to condensed point space
-    points_sorted.sort_by(|a, b| a.x.cmp(&b.x));
-    for point_x in &points_sorted {
-        if let Some(&last_x) = x_map.get(x_map.len().saturating_sub(1))
-            && point_x.x == last_x
-        {
-            // Use the existing last x_map point, so do nothing here
-        } else {
-            // Append a new x_map from this point
-            x_map.push(point_x.x);
+/// How to decide whether a point is inside when the polygon's loops overlap or nest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FillRule {
+    /// Inside if a ray from the point crosses an odd number of edges, so nested loops make holes
+    EvenOdd,
+    /// Inside if the loops wind around the point overall, so holes need to go the other way round
+    NonZero,
+}
+
+/// Classifies points against one or more loops of red tiles joined by straight lines
+/// Works on the original coordinates, so it does not depend on how the map was filled in
+#[derive(Debug)]
+struct PolygonClassifier {
+    /// Every edge of every loop, from one red tile to the next
+    edges: Vec<((u64, u64), (u64, u64))>,
+}
+
+impl PolygonClassifier {
+    fn new(loops: &[Vec<Point>]) -> Self {
+        let mut edges: Vec<((u64, u64), (u64, u64))> = Vec::new();
+        for points in loops {
+            for (i, start) in points.iter().enumerate() {
+                let end = &points[(i + 1) % points.len()];
+                // Lines cannot be diagonal
+                assert!(start.x == end.x || start.y == end.y);
+                edges.push(((start.x, start.y), (end.x, end.y)));
+            }
         }
+        PolygonClassifier { edges }
     }
 
-    // Map Y coordinates to condensed point space
-    points_sorted.sort_by(|a, b| a.y.cmp(&b.y));
-    for point_y in points_sorted {
-        if let Some(&last_y) = y_map.get(y_map.len().saturating_sub(1))
-            && point_y.y == last_y
-        {
-            // Use the existing last x_map point, so do nothing here
+    /// Returns Red or Green for points on an edge, otherwise Inside or Outside
+    ///
+    /// Sends a ray east from the point and looks at the vertical edges it crosses:
+    ///
+    /// ```text
+    ///   #---#   #---#
+    ///   |   |   |   |
+    ///   | x-|---|-->|     x crosses 3 edges going east, so it is inside
+    ///   |   #---#   |
+    ///   #-----------#
+    /// ```
+    ///
+    /// Each edge covers the rows from its lower end up to but not including its upper end, so
+    /// a ray through a corner or along a horizontal edge is only counted once, and collinear
+    /// or repeated red tiles make no difference
+    fn classify(&self, x: u64, y: u64, rule: FillRule) -> Square {
+        let mut crossings: usize = 0;
+        let mut winding: i64 = 0;
+
+        for &((x1, y1), (x2, y2)) in &self.edges {
+            if (x, y) == (x1, y1) || (x, y) == (x2, y2) {
+                return Square::Red;
+            }
+            if (x1.min(x2)..=x1.max(x2)).contains(&x) && (y1.min(y2)..=y1.max(y2)).contains(&y) {
+                return Square::Green;
+            }
+
+            if x1 == x2 && x1 > x && (y1.min(y2)..y1.max(y2)).contains(&y) {
+                crossings += 1;
+                winding += if y2 > y1 { 1 } else { -1 };
+            }
+        }
+
+        let inside = match rule {
+            FillRule::EvenOdd => crossings % 2 == 1,
+            FillRule::NonZero => winding != 0,
+        };
+        if inside {
+            Square::Inside
         } else {
-            // Append a new y_map from this point
-            y_map.push(point_y.y);
+            Square::Outside
         }
     }
 
+    /// Classifies every square of the condensed map
+    /// Every tile in a square is on the same side, so the square's first tile stands in for it
+    fn classify_map(&self, grid: &CompressedGrid, rule: FillRule) -> Vec<Vec<Square>> {
+        (0..grid.y.len())
+            .map(|y| {
+                let y = *grid.y.decompress(y).start();
+                (0..grid.x.len())
+                    .map(|x| self.classify(*grid.x.decompress(x).start(), y, rule))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Number of squares where the two maps disagree on whether the square is in the polygon
+fn count_mismatches(squares: &[Vec<Square>], other: &[Vec<Square>]) -> usize {
+    let filled = |square: &Square| *square != Square::Outside && *square != Square::Unknown;
+    squares
+        .iter()
+        .zip(other)
+        .flat_map(|(row, other_row)| row.iter().zip(other_row))
+        .filter(|(a, b)| filled(a) != filled(b))
+        .count()
+}
+
+/// Splits the input into loops of red tiles, with a blank line between each loop
+fn parse_loops(input: &str) -> Vec<Vec<Point>> {
+    input
+        .split("\n\n")
+        .map(|block| {
+            block
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(Point::from)
+                .collect()
+        })
+        .filter(|points: &Vec<Point>| !points.is_empty())
+        .collect()
+}
+
+/// Splits the points from every loop, one loop after another, back into the loops
+fn split_loops<'a>(points: &'a [Point], loops: &[Vec<Point>]) -> Vec<&'a [Point]> {
+    let mut start = 0;
+    loops
+        .iter()
+        .map(|points_in_loop| {
+            start += points_in_loop.len();
+            &points[start - points_in_loop.len()..start]
+        })
+        .collect()
+}
+
+/// Maps the points to a condensed space of coordinates corresponding to an index of the map
+/// Runs of coordinates between the points get a single square each, so squares stand in for
+/// a known number of tiles and every tile in a square is inside or outside together
+///
+/// Ex:
+///  012345678 ->  01234
+/// 0....#....    0..#..
+/// 1......#..    1....#
+/// 2.........    2.....
+/// 3.#.......    3#....
+///
+fn condense_point_space(points: &mut [Point]) -> CompressedGrid {
+    let grid = CompressedGrid::new(points.iter().map(|point| (point.x, point.y)));
+
     // Populate the map coordinates of all the points
     for point in points {
-        point.map_x = x_map.iter().position(|&b| b == point.x).unwrap();
-        point.map_y = y_map.iter().position(|&b| b == point.y).unwrap();
+        (point.map_x, point.map_y) = grid.compress((point.x, point.y)).unwrap();
     }
 
-    (x_map, y_map)
+    grid
+}
+
+/// Number of tiles in the squares that are inside or on the polygon
+fn covered_area(squares: &[Vec<Square>], grid: &CompressedGrid) -> u64 {
+    squares
+        .iter()
+        .enumerate()
+        .flat_map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(x, square)| ((x, y), square))
+        })
+        .filter(|(_, square)| **square != Square::Outside && **square != Square::Unknown)
+        .map(|(cell, _)| grid.cell_area(cell))
+        .sum()
 }
 
-fn form_lines<'a>(points: &'a Vec<Point>) -> Vec<Line<'a>> {
+fn form_lines<'a>(points: &'a [Point]) -> Vec<Line<'a>> {
     let count = points.len();
     let mut lines: Vec<Line<'_>> = Vec::with_capacity(count);
 
@@ -498,15 +924,9 @@ fn form_lines<'a>(points: &'a Vec<Point>) -> Vec<Line<'a>> {
     lines
 }
 
+/// Red tiles from every loop, one loop after another
 fn parse_input(input: &str) -> Vec<Point> {
-    let lines = input.lines();
-    let mut points: Vec<Point> = Vec::with_capacity(500);
-
-    for line in lines {
-        points.push(Point::from(line));
-    }
-
-    points
+    parse_loops(input).concat()
 }
 
 fn print_squares(squares: &Vec<Vec<Square>>) {
@@ -519,199 +939,667 @@ fn print_squares(squares: &Vec<Vec<Square>>) {
     }
 }
 
-fn test_points(points: &Vec<Point>, x_map: &Vec<u64>, y_map: &Vec<u64>) {
-    for point in points {
-        assert_eq!(x_map[point.map_x], point.x);
-        assert_eq!(y_map[point.map_y], point.y);
+impl Square {
+    /// Colour the square is drawn in when rendering
+    fn colour(&self) -> [u8; 3] {
+        match self {
+            Square::Red => [0xd0, 0x20, 0x20],
+            Square::Green => [0x20, 0xa0, 0x40],
+            Square::Inside => [0xb0, 0xe0, 0xb0],
+            Square::Outside => [0xff, 0xff, 0xff],
+            Square::Unknown => [0x80, 0x80, 0x80],
+            Square::Rectangle => [0x40, 0x60, 0xe0],
+        }
     }
-    println!("Points pass");
 }
 
-fn test_map_monotonic(map: &Vec<u64>) {
-    for i in 0..map.len() {
-        for j in 0..i {
-            assert!(map[i] > map[j]);
+/// Colour candidate rectangles are outlined in
+const CANDIDATE_COLOUR: [u8; 3] = [0xff, 0x90, 0x00];
+
+/// The n largest rectangles with red corners, whether or not they fit in the polygon
+fn top_candidates(areas: &[Area], points: &[Point], n: usize) -> Vec<Rectangle> {
+    areas
+        .iter()
+        .take(n)
+        .map(|area| Rectangle {
+            corner1: points[area.p1_idx],
+            corner2: points[area.p2_idx],
+            area: area.area,
+        })
+        .collect()
+}
+
+/// Draws the polygon in its original coordinates as an SVG image
+/// The inside is filled in from the condensed map, with each square stretched over the tiles
+/// it stands in for, then the edges, red tiles, candidates and chosen rectangle go on top
+fn render_svg(
+    loops: &[&[Point]],
+    squares: &[Vec<Square>],
+    grid: &CompressedGrid,
+    chosen: Option<&Rectangle>,
+    candidates: &[Rectangle],
+) -> String {
+    let (width, height) = (
+        grid.x
+            .len()
+            .checked_sub(1)
+            .map_or(0, |x| grid.x.decompress(x).end() + 1),
+        grid.y
+            .len()
+            .checked_sub(1)
+            .map_or(0, |y| grid.y.decompress(y).end() + 1),
+    );
+    // Scale lines and dots so they show up at any size
+    let stroke = (width.max(height) / 500).max(1);
+
+    let mut out = String::new();
+    out.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\">\n",
+        width, height
+    ));
+
+    // Inside and on the polygon, joining runs of squares along each row
+    let filled = |square: Square| square != Square::Outside && square != Square::Unknown;
+    for (y, row) in squares.iter().enumerate() {
+        // Each tile is drawn as a square centred on its coordinates
+        let top = *grid.y.decompress(y).start() as f64 - 0.5;
+        let height = grid.y.width(y);
+        let mut x = 0;
+        while x < row.len() {
+            if !filled(row[x]) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < row.len() && filled(row[x]) {
+                x += 1;
+            }
+            let left = *grid.x.decompress(start).start() as f64 - 0.5;
+            out.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                left,
+                top,
+                grid.x.span(start, x - 1),
+                height,
+                hex(Square::Inside.colour())
+            ));
         }
     }
-}
 
-fn _line_check_rectangle_within_polygon(
-    p1: &Point,
-    p2: &Point,
-    squares: &Vec<Vec<Square>>,
-    lines: &Vec<Line>,
-    map_check: bool,
-) -> bool {
-    let mut line_check = true;
-    // Check using the lines - if any of the 4 rectangle lie segments intersects another
-    let p21 = &Point {
-        x: p2.x,
-        y: p1.y,
-        map_x: p2.map_x,
-        map_y: p1.map_y,
+    // Edges and red tiles
+    for points in loops {
+        let corners: Vec<String> = points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+        out.push_str(&format!(
+            "<polygon points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+            corners.join(" "),
+            hex(Square::Green.colour()),
+            stroke
+        ));
+    }
+    for point in loops.iter().copied().flatten() {
+        out.push_str(&format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>\n",
+            point.x,
+            point.y,
+            stroke * 2,
+            hex(Square::Red.colour())
+        ));
+    }
+
+    let rect = |rectangle: &Rectangle, style: String| {
+        let (left, top) = (
+            rectangle.corner1.x.min(rectangle.corner2.x),
+            rectangle.corner1.y.min(rectangle.corner2.y),
+        );
+        let (right, bottom) = (
+            rectangle.corner1.x.max(rectangle.corner2.x),
+            rectangle.corner1.y.max(rectangle.corner2.y),
+        );
+        // Covering the whole of the corner tiles, like the filled squares
+        format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>\n",
+            left as f64 - 0.5,
+            top as f64 - 0.5,
+            right - left + 1,
+            bottom - top + 1,
+            style
+        )
     };
-    let p12 = &Point {
-        x: p1.x,
-        y: p2.y,
-        map_x: p1.map_x,
-        map_y: p2.map_y,
+    for candidate in candidates {
+        out.push_str(&rect(
+            candidate,
+            format!(
+                "fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-dasharray=\"{}\"",
+                hex(CANDIDATE_COLOUR),
+                stroke,
+                stroke * 4
+            ),
+        ));
+    }
+    if let Some(chosen) = chosen {
+        out.push_str(&rect(
+            chosen,
+            format!(
+                "fill=\"{}\" fill-opacity=\"0.4\" stroke=\"{}\" stroke-width=\"{}\"",
+                hex(Square::Rectangle.colour()),
+                hex(Square::Rectangle.colour()),
+                stroke * 2
+            ),
+        ));
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Draws the condensed map as a PNG image, with each square as a block of scale x scale pixels
+/// Candidate rectangles are outlined and the chosen rectangle is filled in
+fn render_png(
+    squares: &[Vec<Square>],
+    scale: usize,
+    chosen: Option<&Rectangle>,
+    candidates: &[Rectangle],
+) -> Vec<u8> {
+    let mut colours: Vec<Vec<[u8; 3]>> = squares
+        .iter()
+        .map(|row| row.iter().map(|square| square.colour()).collect())
+        .collect();
+
+    let bounds = |rectangle: &Rectangle| {
+        let (p1, p2) = (&rectangle.corner1, &rectangle.corner2);
+        (
+            p1.map_x.min(p2.map_x),
+            p1.map_x.max(p2.map_x),
+            p1.map_y.min(p2.map_y),
+            p1.map_y.max(p2.map_y),
+        )
     };
-    let rect_lines: [Line; 4] = [
-        Line::new(p1, p12),
-        Line::new(p12, p2),
-        Line::new(p2, p21),
-        Line::new(p21, p1),
-    ];
-    for rect_line in &rect_lines {
-        // Check if this line intersects any line in the list
-        for line in lines {
-            if _do_lines_cross_through(&rect_line, &line) {
-                // println!("Lines cross through!\n{:?}\n{:?}", rect_line, line);
-                line_check = false;
-                break;
+    for candidate in candidates {
+        let (left, right, top, bottom) = bounds(candidate);
+        for (y, row) in colours.iter_mut().enumerate().take(bottom + 1).skip(top) {
+            for (x, colour) in row.iter_mut().enumerate().take(right + 1).skip(left) {
+                if x == left || x == right || y == top || y == bottom {
+                    *colour = CANDIDATE_COLOUR;
+                }
+            }
+        }
+    }
+    if let Some(chosen) = chosen {
+        let (left, right, top, bottom) = bounds(chosen);
+        for (y, row) in colours.iter_mut().enumerate().take(bottom + 1).skip(top) {
+            for (x, colour) in row.iter_mut().enumerate().take(right + 1).skip(left) {
+                // Red and green tiles show through, like draw_rectangle
+                *colour = match squares[y][x] {
+                    square @ (Square::Red | Square::Green) => square.colour(),
+                    _ => Square::Rectangle.colour(),
+                };
             }
         }
     }
 
-    // The line check still has a bug
-    if line_check != map_check {
-        println!(
-            "\nis_rectangle_within_polygon: Line check ({}) and map check ({}) do not line up!",
-            line_check, map_check
-        );
-        println!("{:?}\n{:?}", p1, p2);
-        for (i, rect_line) in rect_lines.iter().enumerate() {
-            println!("{}: {:?}", i, rect_line);
-            // Check if this line intersects any line in the list
-            for line in lines {
-                if _do_lines_cross_through(&rect_line, &line) {
-                    println!("{}: lines cross!\n{:?}\n{:?}", i, rect_line, line);
+    // Raw scanlines, each starting with filter type 0 (none)
+    let width = colours.first().map_or(0, |row| row.len()) * scale;
+    let height = colours.len() * scale;
+    let mut raw: Vec<u8> = Vec::with_capacity((width * 3 + 1) * height);
+    for row in &colours {
+        for _ in 0..scale {
+            // Filter type
+            raw.extend_from_slice(&[0]);
+            for colour in row {
+                for _ in 0..scale {
+                    raw.extend_from_slice(colour);
                 }
             }
         }
+    }
 
-        let mut draw_squares = squares.clone();
-        draw_rectangle(&p1, &p2, &mut draw_squares);
-        print_squares(&draw_squares);
-    }
-    return line_check;
-}
-
-/// Returns true if the lines fully cross through each other
-///   |          --|---   ---     --=---
-/// ------         |        ---
-///   |            |        no      no
-/// L1: (2,0)--(2,5) L2: (1,2)--(4,2)
-fn _do_lines_cross_through(l1: &Line, l2: &Line) -> bool {
-    // (59, 65)--(59, 192)
-    // (57, 181)--(60, 181)
-    // Should be true because:
-    //   65 < 181 and 192 > 181 and 59 >= 57 and 59 <= 60
-    // println!("l1: {:?}\nl2: {:?}", l1, l2);
-    (l1.start.x < l2.start.x // 59 < 57 nope
-        && l1.end.x > l2.end.x
-        && l1.start.y >= l2.start.y
-        && l1.start.y <= l2.end.y)
-        || (l1.start.y < l2.start.y // 65 < 181 yep
-            && l1.end.y > l2.end.y // 192 > 181 yep
-            && l1.start.x >= l2.start.x // 59 >= 60 nope
-            && l1.start.x <= l2.end.x) //  59 <= 60 nope
-    // Check if l1 is horizontal or vertical
-    // match l1.update {
-    //     (1, 0) => {
-    //         // (0, 1) => {
-    //         // Horizontal
-    //         l1.start.x < l2.start.x
-    //             && l1.end.x > l2.end.x
-    //             && l1.start.y >= l2.end.y
-    //             && l1.start.y <= l2.end.y
-    //     }
-    //     (0, 1) => {
-    //         // (1, 0) => {
-    //         // Vertical
-    //         l1.start.y < l2.start.y
-    //             && l1.end.y > l2.end.y
-    //             && l1.start.x >= l2.end.x
-    //             && l1.start.x <= l2.end.x
-    //     }
-    //     _ => {
-    //         panic!("Line must be horizontal or vertical");
-    //     }
-    // }
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png: Vec<u8> = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    write_png_chunk(&mut png, b"IHDR", &header);
+    write_png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&[kind.as_slice(), data].concat());
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps the data in a zlib stream without compressing it, as deflate blocks of up to 64KiB
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if data.is_empty() {
+        vec![data]
+    } else {
+        data.chunks(u16::MAX as usize).collect()
+    };
+    for (i, block) in blocks.iter().enumerate() {
+        out.push(u8::from(i + 1 == blocks.len()));
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    // Adler-32 checksum of the uncompressed data
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+/// CRC-32 as used by PNG chunks
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// Runs the whole of the old approach: sorted areas checked square by square
+    fn solve_both(input: &str) -> (u64, Rectangle) {
+        let mut points = parse_input(input);
+        let loops = parse_loops(input);
+        let areas = get_areas(&points);
+        let grid = condense_point_space(&mut points);
+        let mut lines: Vec<Line> = split_loops(&points, &loops)
+            .into_iter()
+            .flat_map(form_lines)
+            .collect();
+        let mut squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
+        if loops.len() > 1 {
+            squares = PolygonClassifier::new(&loops).classify_map(&grid, FillRule::EvenOdd);
+        }
+        let edges = PolygonEdges::new(&split_loops(&points, &loops));
+
+        let rectangle =
+            largest_inscribed_rectangle(&points, &grid, &squares, &edges, RectangleCheck::Map)
+                .unwrap();
+        let (_, area) =
+            _largest_constrained_area(&areas, &points, &squares, &edges, RectangleCheck::Map);
+        (area, rectangle)
+    }
+
     #[test]
-    fn test_line_cross_through() {
-        let l1 = Line {
-            start: &Point {
-                x: 59,
-                y: 65,
-                map_x: 59,
-                map_y: 65,
-            },
-            end: &Point {
-                x: 59,
-                y: 192,
-                map_x: 59,
-                map_y: 192,
-            },
-            current: (0, 0),
-            update: (0, 1),
-        };
-        let l2 = Line {
-            start: &Point {
-                x: 57,
-                y: 181,
-                map_x: 57,
-                map_y: 181,
-            },
-            end: &Point {
-                x: 60,
-                y: 181,
-                map_x: 60,
-                map_y: 181,
-            },
-            current: (0, 0),
-            update: (1, 0),
-        };
-        assert!(_do_lines_cross_through(&l1, &l2));
+    fn test_largest_inscribed_rectangle() {
+        let (area, rectangle) = solve_both(include_str!("day9-test.txt"));
+        assert_eq!(area, 24);
+        assert_eq!(rectangle.area, 24);
+        let mut corners = [
+            (rectangle.corner1.x, rectangle.corner1.y),
+            (rectangle.corner2.x, rectangle.corner2.y),
+        ];
+        corners.sort();
+        assert_eq!(corners, [(2, 3), (9, 5)]);
+
+        let (area, rectangle) = solve_both(include_str!("day9-input.txt"));
+        assert_eq!(area, 1429075575);
+        assert_eq!(rectangle.area, 1429075575);
+        assert_eq!(rectangle.corner1.area(&rectangle.corner2), rectangle.area);
+    }
+
+    #[test]
+    fn test_solve_with_hole() {
+        // The square with a square hole from test_classifier_holes_and_collinear,
+        // with a blank line between the loops
+        let input = "0,0\n4,0\n9,0\n9,4\n9,6\n0,6\n\n2,2\n2,4\n5,4\n5,2\n";
+        let mut points = parse_input(input);
+        let loops = parse_loops(input);
+        assert_eq!(points.len(), 10);
+        assert_eq!(
+            split_loops(&points, &loops)
+                .iter()
+                .map(|points| points.len())
+                .collect::<Vec<usize>>(),
+            vec![6, 4]
+        );
+
+        // The whole square would take in the hole, the strip below it is the biggest that fits
+        let (area, rectangle) = solve_both(input);
+        assert_eq!(area, 30);
+        assert_eq!(rectangle.area, 30);
+        let mut corners = [
+            (rectangle.corner1.x, rectangle.corner1.y),
+            (rectangle.corner2.x, rectangle.corner2.y),
+        ];
+        corners.sort();
+        assert_eq!(corners, [(0, 6), (9, 4)]);
+
+        // The edges of the hole face into it
+        let edges = PolygonEdges::new(&split_loops(&points, &loops));
+        assert!(!edges.contains_rectangle(&points[0], &points[4]));
+        assert!(edges.contains_rectangle(&points[5], &points[3]));
+        assert!(edges.contains_rectangle(&points[1], &points[9]));
+
+        let grid = condense_point_space(&mut points);
+        let squares = PolygonClassifier::new(&loops).classify_map(&grid, FillRule::EvenOdd);
+        let svg = render_svg(&split_loops(&points, &loops), &squares, &grid, None, &[]);
+        assert_eq!(svg.matches("<polygon ").count(), 2);
+        assert!(svg.contains("<polygon points=\"2,2 2,4 5,4 5,2\""));
+        assert_eq!(svg.matches("<circle ").count(), 10);
+    }
+
+    #[test]
+    fn test_checks_agree_on_rectangle() {
+        for input in [
+            include_str!("day9-test.txt"),
+            include_str!("day9-input.txt"),
+        ] {
+            let mut points = parse_input(input);
+            let grid = condense_point_space(&mut points);
+            let mut lines = form_lines(&points);
+            let squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
+            let edges = PolygonEdges::new(&[&points]);
+
+            for check in [RectangleCheck::Map, RectangleCheck::Edges] {
+                let rectangle =
+                    largest_inscribed_rectangle(&points, &grid, &squares, &edges, check).unwrap();
+                let (c1, c2) = (rectangle.corner1, rectangle.corner2);
+                // Either check accepts the rectangle the other one picks
+                for other in [RectangleCheck::Map, RectangleCheck::Edges] {
+                    assert!(is_rectangle_within_polygon(
+                        &c1, &c2, &squares, &edges, other
+                    ));
+                }
+                // The map squares under the rectangle cover just the rectangle's tiles
+                assert_eq!(c1.area(&c2), rectangle.area);
+            }
+        }
+    }
+
+    #[test]
+    fn test_condensed_points() {
+        let mut points = parse_input(include_str!("day9-input.txt"));
+        let grid = condense_point_space(&mut points);
+        // Every red tile gets a cell of its own
+        for point in &points {
+            assert_eq!(grid.x.decompress(point.map_x), point.x..=point.x);
+            assert_eq!(grid.y.decompress(point.map_y), point.y..=point.y);
+        }
+        // Cells go up in order with nothing missed out between them
+        for axis in [&grid.x, &grid.y] {
+            for idx in 1..axis.len() {
+                assert_eq!(
+                    *axis.decompress(idx).start(),
+                    axis.decompress(idx - 1).end() + 1
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_outside_counts() {
+        use Square::*;
+        let squares = vec![
+            vec![Outside, Red, Green],
+            vec![Outside, Inside, Outside],
+            vec![Red, Outside, Outside],
+        ];
+        let outside = OutsideCounts::new(&squares);
+        assert_eq!(outside.count((0, 0), (2, 2)), 5);
+        assert_eq!(outside.count((1, 0), (1, 1)), 0);
+        assert_eq!(outside.count((2, 2), (1, 1)), 3);
+        assert_eq!(outside.count((0, 2), (0, 2)), 0);
+    }
+
+    #[test]
+    fn test_classifier_matches_map() {
+        for input in [
+            include_str!("day9-test.txt"),
+            include_str!("day9-input.txt"),
+        ] {
+            let mut points = parse_input(input);
+            let grid = condense_point_space(&mut points);
+            let mut lines = form_lines(&points);
+            let squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
+
+            let classifier = PolygonClassifier::new(&parse_loops(input));
+            for rule in [FillRule::EvenOdd, FillRule::NonZero] {
+                let classified = classifier.classify_map(&grid, rule);
+                assert_eq!(count_mismatches(&squares, &classified), 0);
+                for (row, classified_row) in squares.iter().zip(&classified) {
+                    for (square, classified) in row.iter().zip(classified_row) {
+                        if *square == Square::Red {
+                            assert_eq!(*classified, Square::Red);
+                        }
+                    }
+                }
+            }
+        }
+    }
 
-        let l3 = Line {
-            start: &Point {
-                x: 2,
-                y: 0,
-                map_x: 2,
+    #[test]
+    fn test_classifier_holes_and_collinear() {
+        // A square with a square hole, with extra red tiles part way along two edges
+        //   0123456789
+        // 0 #---#----#
+        // 1 |        |
+        // 2 | #--#   |
+        // 3 | |  |   |
+        // 4 | #--#   #
+        // 5 |        |
+        // 6 #--------#
+        let outer = "0,0\n4,0\n9,0\n9,4\n9,6\n0,6";
+        let hole = "2,2\n2,4\n5,4\n5,2";
+        let loops = parse_loops(&format!("{}\n\n{}\n", outer, hole));
+        assert_eq!(loops.len(), 2);
+        let classifier = PolygonClassifier::new(&loops);
+
+        for rule in [FillRule::EvenOdd, FillRule::NonZero] {
+            assert_eq!(classifier.classify(1, 1, rule), Square::Inside);
+            assert_eq!(classifier.classify(3, 3, rule), Square::Outside);
+            assert_eq!(classifier.classify(7, 3, rule), Square::Inside);
+            // Rays along the hole's edges and through the collinear tile at (9, 4)
+            assert_eq!(classifier.classify(1, 2, rule), Square::Inside);
+            assert_eq!(classifier.classify(6, 4, rule), Square::Inside);
+            assert_eq!(classifier.classify(10, 3, rule), Square::Outside);
+            assert_eq!(classifier.classify(4, 0, rule), Square::Red);
+            assert_eq!(classifier.classify(3, 4, rule), Square::Green);
+            assert_eq!(classifier.classify(9, 5, rule), Square::Green);
+        }
+
+        // A hole going the same way round as the outside is filled in by the winding rule
+        let same_way = "2,2\n5,2\n5,4\n2,4";
+        let classifier =
+            PolygonClassifier::new(&parse_loops(&format!("{}\n\n{}", outer, same_way)));
+        assert_eq!(
+            classifier.classify(3, 3, FillRule::EvenOdd),
+            Square::Outside
+        );
+        assert_eq!(classifier.classify(3, 3, FillRule::NonZero), Square::Inside);
+    }
+
+    #[test]
+    fn test_edge_check_matches_map() {
+        for input in [
+            include_str!("day9-test.txt"),
+            include_str!("day9-input.txt"),
+        ] {
+            let mut points = parse_input(input);
+            let grid = condense_point_space(&mut points);
+            let mut lines = form_lines(&points);
+            let squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
+            let edges = PolygonEdges::new(&[&points]);
+            let outside = OutsideCounts::new(&squares);
+
+            for (l, p1) in points.iter().enumerate() {
+                for p2 in &points[..l] {
+                    let map_check = outside.count((p1.map_x, p1.map_y), (p2.map_x, p2.map_y)) == 0;
+                    assert_eq!(
+                        edges.contains_rectangle(p1, p2),
+                        map_check,
+                        "{:?} {:?}",
+                        p1,
+                        p2
+                    );
+                }
+            }
+
+            assert_eq!(
+                largest_inscribed_rectangle(
+                    &points,
+                    &grid,
+                    &squares,
+                    &edges,
+                    RectangleCheck::Edges
+                ),
+                largest_inscribed_rectangle(&points, &grid, &squares, &edges, RectangleCheck::Map)
+            );
+        }
+    }
+
+    #[test]
+    fn test_edge_check_touching() {
+        // A polygon with a gap one tile wide at the top, and a notch out of the bottom right
+        //   0123456789
+        // 0 #-#.#----#
+        // 1 | | |    |
+        // 2 | #-#  #-#
+        // 3 |      |
+        // 4 #------#
+        let points = parse_input("0,0\n2,0\n2,2\n4,2\n4,0\n9,0\n9,2\n7,2\n7,4\n0,4");
+        let edges = PolygonEdges::new(&[&points]);
+        let fits = |(x1, y1): (u64, u64), (x2, y2): (u64, u64)| {
+            let corner = |x, y| Point {
+                x,
+                y,
+                map_x: 0,
                 map_y: 0,
-            },
-            end: &Point {
-                x: 2,
-                y: 5,
-                map_x: 2,
-                map_y: 5,
-            },
-            current: (0, 0),
-            update: (0, 1),
+            };
+            edges.contains_rectangle(&corner(x1, y1), &corner(x2, y2))
         };
-        let l4 = Line {
-            start: &Point {
-                x: 1,
-                y: 2,
-                map_x: 1,
-                map_y: 2,
-            },
-            end: &Point {
-                x: 4,
-                y: 2,
-                map_x: 4,
-                map_y: 2,
-            },
-            current: (0, 0),
-            update: (1, 0),
+
+        // Running along edges and into corners
+        assert!(fits((0, 2), (7, 4)));
+        assert!(fits((0, 0), (2, 4)));
+        assert!(fits((4, 0), (9, 2)));
+        assert!(fits((0, 2), (9, 2)));
+        assert!(fits((2, 2), (7, 2)));
+        // Across the gap at x = 3 and past the bottom right corner
+        assert!(!fits((0, 0), (4, 2)));
+        assert!(!fits((0, 0), (7, 4)));
+        assert!(!fits((0, 0), (9, 4)));
+        assert!(!fits((7, 2), (9, 4)));
+        // Entirely outside, and entirely inside without touching an edge
+        assert!(!fits((10, 0), (12, 3)));
+        assert!(fits((1, 1), (1, 3)));
+
+        // The same polygon the other way round
+        let reversed: Vec<Point> = points.iter().rev().copied().collect();
+        let reversed = PolygonEdges::new(&[&reversed]);
+        for edge in &reversed.edges {
+            assert!(
+                edges
+                    .edges
+                    .iter()
+                    .any(|other| other.start == edge.end && other.outward == edge.outward)
+            );
+        }
+
+        // Two edges a single tile apart leave no tiles outside between them
+        let slot = parse_input("0,0\n3,0\n3,3\n4,3\n4,0\n6,0\n6,5\n0,5");
+        let edges = PolygonEdges::new(&[&slot]);
+        let corner = |x, y| Point {
+            x,
+            y,
+            map_x: 0,
+            map_y: 0,
+        };
+        assert!(edges.contains_rectangle(&corner(0, 0), &corner(6, 5)));
+        let wide = parse_input("0,0\n3,0\n3,3\n5,3\n5,0\n6,0\n6,5\n0,5");
+        let edges = PolygonEdges::new(&[&wide]);
+        assert!(!edges.contains_rectangle(&corner(0, 0), &corner(6, 5)));
+    }
+
+    #[test]
+    fn test_render() {
+        let input = include_str!("day9-test.txt");
+        let mut points = parse_input(input);
+        let areas = get_areas(&points);
+        let grid = condense_point_space(&mut points);
+        let mut lines = form_lines(&points);
+        let squares = populate_map(&mut lines, grid.x.len(), grid.y.len());
+        let edges = PolygonEdges::new(&[&points]);
+        let rectangle =
+            largest_inscribed_rectangle(&points, &grid, &squares, &edges, RectangleCheck::Map)
+                .unwrap();
+        let candidates = top_candidates(&areas, &points, 3);
+        assert_eq!(candidates[0].area, 50);
+
+        let svg = render_svg(&[&points], &squares, &grid, Some(&rectangle), &candidates);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 12 8\">"));
+        assert!(svg.contains("<polygon points=\"7,1 11,1 11,7 9,7 9,5 2,5 2,3 7,3\""));
+        assert_eq!(svg.matches("<circle ").count(), 8);
+        assert_eq!(svg.matches("stroke-dasharray").count(), 3);
+        assert!(
+            svg.contains("<rect x=\"1.5\" y=\"2.5\" width=\"8\" height=\"3\" fill=\"#4060e0\"")
+        );
+
+        let png = render_png(&squares, 2, Some(&rectangle), &candidates);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 14);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 14);
+
+        // Check every chunk and read back the pixels from the stored deflate blocks
+        let mut at = 8;
+        let mut pixels: Vec<u8> = Vec::new();
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let chunk = &png[at + 4..at + 8 + len];
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc32(chunk), crc);
+            if &chunk[..4] == b"IDAT" {
+                let zlib = &chunk[4..];
+                let mut block = 2;
+                loop {
+                    let len = u16::from_le_bytes([zlib[block + 1], zlib[block + 2]]) as usize;
+                    pixels.extend_from_slice(&zlib[block + 5..block + 5 + len]);
+                    if zlib[block] == 1 {
+                        break;
+                    }
+                    block += 5 + len;
+                }
+            }
+            at += 12 + len;
+        }
+        assert_eq!(pixels.len(), (14 * 3 + 1) * 14);
+        let pixel = |x: usize, y: usize| {
+            let start = y * (14 * 3 + 1) + 1 + x * 3;
+            [pixels[start], pixels[start + 1], pixels[start + 2]]
         };
-        assert!(_do_lines_cross_through(&l3, &l4));
+        // The largest candidate runs round the edge of the map
+        assert_eq!(pixel(0, 0), CANDIDATE_COLOUR);
+        // The chosen rectangle fills in (9, 3) but leaves the red tile at (2, 3)
+        assert_eq!(pixel(8, 4), Square::Rectangle.colour());
+        assert_eq!(pixel(9, 5), Square::Rectangle.colour());
+        assert_eq!(pixel(0, 4), Square::Red.colour());
+
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
     }
 }
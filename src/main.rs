@@ -1,3 +1,5 @@
+mod combinatorics;
+mod compress;
 mod day1;
 mod day10;
 mod day11;
@@ -10,6 +12,8 @@ mod day6;
 mod day7;
 mod day8;
 mod day9;
+mod rng;
+mod settings;
 
 fn main() {
     day1::solve_day1();
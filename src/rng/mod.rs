@@ -0,0 +1,22 @@
+/// Small xorshift random number generator, so nothing needs a dependency for randomness
+pub(crate) struct XorShift(u64);
+
+impl XorShift {
+    /// A state of 0 would never change, so it gets bumped up to 1
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Random number below the bound
+    #[cfg(test)]
+    pub(crate) fn next_below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
@@ -0,0 +1,36 @@
+use std::env;
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Directory for files to look at outside the program, like renders and exports
+/// Set with AOC_OUTPUT_DIR, nothing gets written when it isn't set
+const OUTPUT_DIR: &str = "AOC_OUTPUT_DIR";
+
+/// Where to write the named file, or None if nothing should be written
+pub(crate) fn output_path(name: &str) -> Option<PathBuf> {
+    env::var_os(OUTPUT_DIR).map(|dir| PathBuf::from(dir).join(name))
+}
+
+/// Writes the file, printing where it went or why it couldn't be written
+pub(crate) fn write_output(path: PathBuf, contents: impl AsRef<[u8]>) {
+    match std::fs::write(&path, contents) {
+        Ok(()) => println!("Wrote {}", path.display()),
+        Err(err) => println!("Could not write {}: {}", path.display(), err),
+    }
+}
+
+/// Reads a setting from the environment variable, or the default if it isn't set
+/// A value that doesn't parse is reported and the default used instead
+pub(crate) fn setting<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: Debug,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|err| {
+            println!("Ignoring {}={}: {:?}", name, value, err);
+            default
+        }),
+        Err(_) => default,
+    }
+}